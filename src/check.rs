@@ -0,0 +1,392 @@
+//! Consistency checks of the keys of a model, to find and remove entries
+//! left behind by interrupted writes or changes made outside the library.
+
+use std::collections::{HashMap, HashSet};
+
+use redis;
+
+use lua::REPAIR;
+use trace::traced;
+use {decode_hash, lua_script, Connection, Ohmer, OhmerError};
+
+/// Problems found by `check`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    /// Ids in `Class:all` or `Class:deleted` without a hash.
+    pub missing: Vec<usize>,
+    /// Ids with a hash, but not in `Class:all` nor in `Class:deleted`. A
+    /// repair adds them back to `Class:all`, keeping their data; `reindex`
+    /// rebuilds the indices they lost.
+    pub orphans: Vec<usize>,
+    /// Index sets and the ids in them that are not in `Class:all`.
+    pub dangling_indices: Vec<(String, usize)>,
+    /// Unique hashes and the values in them whose id is not in `Class:all`,
+    /// or not recorded in the `_uniques` key of the object.
+    pub dangling_uniques: Vec<(String, String)>,
+    /// Unique fields, values and the ids of the objects sharing them.
+    pub unique_collisions: Vec<(String, String, Vec<usize>)>,
+    /// Sorted, prefix and geo index keys, and `Class:expires`, with the
+    /// members whose id is not in `Class:all`, or, for `Class:expires`,
+    /// neither in `Class:deleted`.
+    pub dangling_sorted: Vec<(String, String)>,
+    /// Entries of the `_indices`, `_uniques` and `_sorted` keys of an
+    /// object that point to an index or unique without the object.
+    pub stale_memos: Vec<(String, String)>,
+    /// Sets, lists, counters and memo keys of ids that are not in
+    /// `Class:all` nor in `Class:deleted`.
+    pub orphan_keys: Vec<String>,
+    /// Ids in `Class:all` whose hash cannot be decoded as the model. They
+    /// are not removed by a repair.
+    pub undecodable: Vec<usize>,
+}
+
+impl Report {
+    /// Checks if no problem was found.
+    pub fn is_clean(&self) -> bool {
+        *self == Report::default()
+    }
+}
+
+/// Scans the keys of a model, `batch` keys or ids per request, and reports
+/// dangling index, unique and sorted set entries, orphan hashes and keys,
+/// and unique collisions. If `repair` is true, orphan hashes are added
+/// back to `Class:all`, and the other entries and keys found are removed,
+/// except for unique collisions and undecodable objects, which are only
+/// reported. Each entry is checked again as it is repaired, so objects
+/// saved or deleted during the check are kept.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::Ohmer;
+/// # use redis::Commands;
+/// model!(
+///     Ledger {
+///         indices { branch:String = "".to_string(); };
+///     });
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let ledger = create!(Ledger { branch: "north".to_string(), }, &client).unwrap();
+/// let _:bool = client.srem("Ledger:all", ledger.id).unwrap();
+///
+/// let report = ohmers::check::<Ledger>(100, true, &client).unwrap();
+/// assert!(report.orphans.contains(&ledger.id));
+/// assert!(report.dangling_indices.contains(&("Ledger:indices:branch:north".to_string(), ledger.id)));
+/// assert!(ohmers::check::<Ledger>(100, false, &client).unwrap().is_clean());
+/// let member:bool = client.sismember("Ledger:all", ledger.id).unwrap();
+/// assert!(member);
+/// # }
+/// ```
+pub fn check<'a, T: Ohmer>(batch: usize, repair: bool, r: impl Connection<'a>) -> Result<Report, OhmerError> {
+    let r = r.connection();
+    let mut report = Report::default();
+    let obj = T::default();
+    let name = obj.get_class_name();
+    let encoder = try!(obj.encoder());
+
+    let mut all = HashSet::new();
+    let mut live = HashSet::new();
+    for set in ["all", "deleted"].iter() {
+        let key = format!("{}:{}", name, set);
+        let ids:Vec<usize> = try!(sscan(&key, batch, r));
+        for ids in ids.chunks(batch) {
+            let mut q = redis::pipe();
+            for id in ids.iter() {
+                q.cmd("EXISTS").arg(format!("{}:{}", name, id));
+            }
+            let exist:Vec<bool> = try!(q.query(&traced(r)));
+            for (id, exists) in ids.iter().zip(exist.into_iter()) {
+                if exists {
+                    live.insert(*id);
+                    if *set == "all" {
+                        all.insert(*id);
+                    }
+                } else {
+                    report.missing.push(*id);
+                }
+            }
+        }
+    }
+
+    let mut uniques = HashMap::new();
+    let mut ids = all.iter().cloned().collect::<Vec<_>>();
+    ids.sort();
+    for ids in ids.chunks(batch) {
+        try!(check_objects::<T>(&name, ids, &mut uniques, &mut report, r));
+    }
+    for ((field, value), mut ids) in uniques.into_iter() {
+        if ids.len() > 1 {
+            ids.sort();
+            report.unique_collisions.push((field, value, ids));
+        }
+    }
+
+    let keys:Vec<String> = try!(scan_keys(&*format!("{}:*", name), batch, r));
+    let indices = format!("{}:indices:", name);
+    let uniques = format!("{}:uniques:", name);
+    let sorted = [format!("{}:sorted:", name), format!("{}:prefix:", name), format!("{}:geo:", name)];
+    let expires = format!("{}:expires", name);
+    let counters = encoder.counters.iter().map(|c| &**c).collect::<HashSet<_>>();
+    let collections = encoder.sets.iter().chain(encoder.lists.iter()).map(|c| &**c).collect::<HashSet<_>>();
+    let mut orphan_keys = vec![];
+    for key in keys.iter() {
+        if key.starts_with(&*indices) {
+            let ids:Vec<usize> = try!(sscan(key, batch, r));
+            for id in ids.into_iter().filter(|id| !all.contains(id)) {
+                report.dangling_indices.push((key.clone(), id));
+            }
+            continue;
+        }
+        if key.starts_with(&*uniques) {
+            try!(check_uniques(&name, key, &all, batch, &mut report, r));
+            continue;
+        }
+        if *key == expires {
+            try!(check_sorted(key, false, &live, batch, &mut report, r));
+            continue;
+        }
+        if sorted.iter().any(|prefix| key.starts_with(&**prefix)) {
+            try!(check_sorted(key, key.starts_with(&*sorted[1]), &all, batch, &mut report, r));
+            continue;
+        }
+        let parts = key[name.len() + 1..].splitn(2, ':').collect::<Vec<_>>();
+        let id = match parts[0].parse::<usize>() {
+            Ok(id) => Some(id),
+            Err(_) if parts.len() == 2 && collections.contains(parts[0]) => parts[1].parse().ok(),
+            Err(_) => None,
+        };
+        let id = match id {
+            Some(id) if !live.contains(&id) => id,
+            _ => continue,
+        };
+        if parts.len() == 1 {
+            report.orphans.push(id);
+        } else if parts[1].starts_with("_") || counters.contains(parts[1]) || collections.contains(parts[0]) {
+            report.orphan_keys.push(key.clone());
+            orphan_keys.push((key.clone(), id));
+        }
+    }
+
+    report.missing.sort();
+    report.orphans.sort();
+    report.unique_collisions.sort();
+    report.undecodable.sort();
+    if repair {
+        try!(fix(&name, &report, &orphan_keys, batch, r));
+    }
+    Ok(report)
+}
+
+/// Loads a batch of objects, adding their unique values to `uniques` and
+/// checking their `_indices`, `_uniques` and `_sorted` keys.
+fn check_objects<T: Ohmer>(name: &str, ids: &[usize], uniques: &mut HashMap<(String, String), Vec<usize>>,
+        report: &mut Report, r: &redis::ConnectionLike) -> Result<(), OhmerError> {
+    let mut q = redis::pipe();
+    for id in ids.iter() {
+        q.cmd("HGETALL").arg(format!("{}:{}", name, id));
+        q.cmd("SMEMBERS").arg(format!("{}:{}:_indices", name, id));
+        q.cmd("HGETALL").arg(format!("{}:{}:_uniques", name, id));
+        q.cmd("HGETALL").arg(format!("{}:{}:_sorted", name, id));
+    }
+    let values:Vec<redis::Value> = try!(q.query(&traced(r)));
+
+    let mut q = redis::pipe();
+    let mut entries = vec![];
+    for (id, values) in ids.iter().zip(values.chunks(4)) {
+        let properties:HashMap<String, Vec<u8>> = try!(redis::from_redis_value(&values[0]));
+        let indices:Vec<String> = try!(redis::from_redis_value(&values[1]));
+        let memos:HashMap<String, String> = try!(redis::from_redis_value(&values[2]));
+        let members:HashMap<String, String> = try!(redis::from_redis_value(&values[3]));
+        if properties.len() == 0 {
+            // deleted after the scan
+            continue;
+        }
+        match decode_hash::<T>(*id, properties) {
+            Ok(obj) => {
+                let encoder = try!(obj.encoder());
+                if let Ok((values, _)) = obj.uniques_indices(&encoder) {
+                    for (field, value) in values.into_iter() {
+                        uniques.entry((field, value)).or_insert_with(Vec::new).push(*id);
+                    }
+                }
+            },
+            // the memo keys are still checked
+            Err(_) => report.undecodable.push(*id),
+        }
+
+        let memo = format!("{}:{}:_indices", name, id);
+        for index in indices.iter() {
+            q.cmd("SISMEMBER").arg(&**index).arg(*id);
+            entries.push((memo.clone(), index.clone(), None));
+        }
+        let memo = format!("{}:{}:_uniques", name, id);
+        for (unique, value) in memos.iter() {
+            q.cmd("HGET").arg(&**unique).arg(&**value);
+            entries.push((memo.clone(), unique.clone(), Some(format!("{}", id))));
+        }
+        let memo = format!("{}:{}:_sorted", name, id);
+        for (key, member) in members.iter() {
+            q.cmd("ZSCORE").arg(&**key).arg(&**member);
+            entries.push((memo.clone(), key.clone(), None));
+        }
+    }
+    if entries.len() == 0 {
+        return Ok(());
+    }
+    let holders:Vec<redis::Value> = try!(q.query(&traced(r)));
+    for (i, (memo, entry, holder)) in entries.into_iter().enumerate() {
+        let valid = match (&holders[i], holder) {
+            (&redis::Value::Int(found), _) => found == 1,
+            (&redis::Value::Nil, _) => false,
+            (value, Some(id)) => redis::from_redis_value::<String>(value).map(|h| h == id).unwrap_or(false),
+            // the score of a sorted set member
+            (_, None) => true,
+        };
+        if !valid {
+            report.stale_memos.push((memo, entry));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the ids in the unique hash `key` are in `Class:all` and
+/// have the value recorded in their `_uniques` key.
+fn check_uniques(name: &str, key: &str, all: &HashSet<usize>, batch: usize, report: &mut Report,
+        r: &redis::ConnectionLike) -> Result<(), OhmerError> {
+    let mut cursor = 0;
+    loop {
+        let (next, values):(u64, Vec<String>) = try!(redis::cmd("HSCAN")
+                .arg(key)
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(batch)
+                .query(&traced(r)));
+        let pairs = values.chunks(2).filter(|pair| pair.len() == 2).collect::<Vec<_>>();
+        let mut q = redis::pipe();
+        for pair in pairs.iter() {
+            q.cmd("HGET").arg(format!("{}:{}:_uniques", name, pair[1])).arg(key);
+        }
+        let memos:Vec<Option<String>> = if pairs.len() > 0 { try!(q.query(&traced(r))) } else { vec![] };
+        for (pair, memo) in pairs.iter().zip(memos.into_iter()) {
+            let indexed = pair[1].parse::<usize>().map(|id| all.contains(&id)).unwrap_or(false);
+            if !indexed || memo.as_ref() != Some(&pair[0]) {
+                report.dangling_uniques.push((key.to_string(), pair[0].clone()));
+            }
+        }
+        if next == 0 {
+            return Ok(());
+        }
+        cursor = next;
+    }
+}
+
+/// Repairs the entries and keys in the report with the REPAIR script,
+/// except unique collisions and undecodable objects. `orphan_keys` has the
+/// id of each orphan key.
+fn fix(name: &str, report: &Report, orphan_keys: &[(String, usize)], batch: usize, r: &redis::ConnectionLike) -> Result<(), OhmerError> {
+    let mut entries = vec![];
+    for id in report.missing.iter() {
+        entries.push(("missing", format!("{}", id), String::new()));
+    }
+    for id in report.orphans.iter() {
+        entries.push(("orphan", format!("{}", id), String::new()));
+    }
+    for &(ref key, id) in report.dangling_indices.iter() {
+        entries.push(("index", key.clone(), format!("{}", id)));
+    }
+    for &(ref key, ref value) in report.dangling_uniques.iter() {
+        entries.push(("unique", key.clone(), value.clone()));
+    }
+    for &(ref key, ref member) in report.dangling_sorted.iter() {
+        entries.push(("sorted", key.clone(), member.clone()));
+    }
+    for &(ref memo, ref entry) in report.stale_memos.iter() {
+        entries.push(("memo", memo.clone(), entry.clone()));
+    }
+    for &(ref key, id) in orphan_keys.iter() {
+        entries.push(("key", key.clone(), format!("{}", id)));
+    }
+    let script = lua_script(REPAIR);
+    for entries in entries.chunks(batch) {
+        let mut args = vec![name.to_string()];
+        for &(kind, ref first, ref second) in entries.iter() {
+            args.push(kind.to_string());
+            args.push(first.clone());
+            args.push(second.clone());
+        }
+        let _:usize = try!(script.arg(args).invoke(&traced(r)));
+    }
+    Ok(())
+}
+
+/// Checks that the ids of the members of the sorted set `key` are in
+/// `ids`. Members of prefix indices end with the id, after the value.
+fn check_sorted(key: &str, prefix: bool, ids: &HashSet<usize>, batch: usize, report: &mut Report,
+        r: &redis::ConnectionLike) -> Result<(), OhmerError> {
+    let mut cursor = 0;
+    loop {
+        let (next, values):(u64, Vec<String>) = try!(redis::cmd("ZSCAN")
+                .arg(key)
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(batch)
+                .query(&traced(r)));
+        for pair in values.chunks(2) {
+            let member = &pair[0];
+            let id = if prefix { member.rsplit(':').next().unwrap() } else { &**member };
+            if !id.parse::<usize>().map(|id| ids.contains(&id)).unwrap_or(false) {
+                report.dangling_sorted.push((key.to_string(), member.clone()));
+            }
+        }
+        if next == 0 {
+            return Ok(());
+        }
+        cursor = next;
+    }
+}
+
+/// Gets all the members of a set with SSCAN.
+fn sscan(key: &str, batch: usize, r: &redis::ConnectionLike) -> Result<Vec<usize>, OhmerError> {
+    let mut members = vec![];
+    let mut cursor = 0;
+    loop {
+        let (next, ids):(u64, Vec<String>) = try!(redis::cmd("SSCAN")
+                .arg(key)
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(batch)
+                .query(&traced(r)));
+        // members that are not ids are ignored
+        members.extend(ids.iter().filter_map(|id| id.parse::<usize>().ok()));
+        if next == 0 {
+            return Ok(members);
+        }
+        cursor = next;
+    }
+}
+
+/// Gets all the keys matching `pattern` with SCAN.
+fn scan_keys(pattern: &str, batch: usize, r: &redis::ConnectionLike) -> Result<Vec<String>, OhmerError> {
+    let mut keys = HashSet::new();
+    let mut cursor = 0;
+    loop {
+        let (next, found):(u64, Vec<String>) = try!(redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(pattern)
+                .arg("COUNT")
+                .arg(batch)
+                .query(&traced(r)));
+        // SCAN may return a key more than once
+        keys.extend(found);
+        if next == 0 {
+            let mut keys = keys.into_iter().collect::<Vec<_>>();
+            keys.sort();
+            return Ok(keys);
+        }
+        cursor = next;
+    }
+}
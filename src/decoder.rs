@@ -4,6 +4,7 @@ extern crate rustc_serialize;
 
 use std::ascii::AsciiExt;
 use std::collections::HashMap;
+use std::mem::replace;
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -11,6 +12,8 @@ enum DecoderStatus {
     Unnamed,
     Normal,
     Reference,
+    Bytes,
+    Timestamp,
 }
 
 #[derive(Debug)]
@@ -30,33 +33,49 @@ impl From<redis::RedisError> for DecoderError {
 type DecodeResult<T> = Result<T, DecoderError>;
 
 pub struct Decoder {
-    properties: HashMap<String, String>,
-    stack: Vec<Option<String>>,
+    properties: HashMap<String, Vec<u8>>,
+    stack: Vec<Option<Vec<u8>>>,
+    bytes: std::vec::IntoIter<u8>,
     status: DecoderStatus,
 }
 
 impl Decoder {
-    pub fn new(properties: HashMap<String, String>) -> Decoder {
+    pub fn new(properties: HashMap<String, Vec<u8>>) -> Decoder {
         Decoder {
             properties: properties,
             stack: vec![],
+            bytes: vec![].into_iter(),
             status: DecoderStatus::Unnamed,
         }
     }
+
+    /// Takes the next value from the stack, failing if it is missing.
+    fn pop_bytes(&mut self, expected: &str) -> DecodeResult<Vec<u8>> {
+        match self.stack.pop() {
+            Some(opt_s) => match opt_s {
+                Some(s) => Ok(s),
+                None => Err(DecoderError::ExpectedError(expected.to_string(), "None".to_string()))
+            },
+            None => Err(DecoderError::ExpectedError(expected.to_string(), "Not found".to_string()))
+        }
+    }
+
+    /// Takes the next value from the stack, failing if it is missing or
+    /// it is not valid UTF-8.
+    fn pop_str(&mut self, expected: &str) -> DecodeResult<String> {
+        let bytes = try!(self.pop_bytes(expected));
+        String::from_utf8(bytes).map_err(|e|
+                DecoderError::ExpectedError(expected.to_string(), String::from_utf8_lossy(e.as_bytes()).into_owned()))
+    }
 }
 
 macro_rules! read_primitive {
     ($name:ident, $ty:ident) => {
         fn $name(&mut self) -> DecodeResult<$ty> {
-            match self.stack.pop() {
-                Some(opt_s) => match opt_s {
-                    Some(s) => match s.parse() {
-                        Ok(v) => Ok(v),
-                        Err(_) => Err(DecoderError::ExpectedError("Number".to_string(), s)),
-                    },
-                    None => Err(DecoderError::ExpectedError("Number".to_string(), "None".to_string()))
-                },
-                None => Err(DecoderError::ExpectedError("Number".to_string(), "Not found".to_string()))
+            let s = try!(self.pop_str("Number"));
+            match s.parse() {
+                Ok(v) => Ok(v),
+                Err(_) => Err(DecoderError::ExpectedError("Number".to_string(), s)),
             }
         }
     }
@@ -70,21 +89,26 @@ impl rustc_serialize::Decoder for Decoder {
     }
 
     fn read_usize(&mut self) -> DecodeResult<usize> {
-        let v = match self.stack.pop() {
-            Some(opt_s) => match opt_s {
-                Some(s) => match s.parse() {
-                    Ok(v) => v,
-                    Err(_) => return Err(DecoderError::ExpectedError("Number".to_string(), s)),
-                },
-                None => return Err(DecoderError::ExpectedError("Number".to_string(), "None".to_string()))
-            },
-            None => return Err(DecoderError::ExpectedError("Number".to_string(), "Not found".to_string()))
+        let s = try!(self.pop_str("Number"));
+        let v = match s.parse() {
+            Ok(v) => v,
+            Err(_) => return Err(DecoderError::ExpectedError("Number".to_string(), s)),
         };
         self.status = DecoderStatus::Normal;
         Ok(v)
     }
 
-    read_primitive! { read_u8, u8 }
+    fn read_u8(&mut self) -> DecodeResult<u8> {
+        if self.status == DecoderStatus::Bytes {
+            return self.bytes.next().ok_or(DecoderError::ExpectedError("Byte".to_string(), "Not found".to_string()));
+        }
+        let s = try!(self.pop_str("Number"));
+        match s.parse() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(DecoderError::ExpectedError("Number".to_string(), s)),
+        }
+    }
+
     read_primitive! { read_u16, u16 }
     read_primitive! { read_u32, u32 }
     read_primitive! { read_u64, u64 }
@@ -97,16 +121,11 @@ impl rustc_serialize::Decoder for Decoder {
     read_primitive! { read_f64, f64 }
 
     fn read_bool(&mut self) -> DecodeResult<bool> {
-        match self.stack.pop() {
-            Some(opt_s) => match opt_s {
-                Some(s) => match &*s {
-                    "0" => Ok(false),
-                    "1" => Ok(true),
-                    _ => Err(DecoderError::ExpectedError("Boolean".to_string(), s)),
-                },
-                None => Err(DecoderError::ExpectedError("Boolean".to_string(), "None".to_string()))
-            },
-            None => Err(DecoderError::ExpectedError("Boolean".to_string(), "Not found".to_string()))
+        let s = try!(self.pop_str("Boolean"));
+        match &*s {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            _ => Err(DecoderError::ExpectedError("Boolean".to_string(), s)),
         }
     }
 
@@ -115,13 +134,7 @@ impl rustc_serialize::Decoder for Decoder {
     }
 
     fn read_str(&mut self) -> DecodeResult<String> {
-        match self.stack.pop() {
-            Some(opt_s) => match opt_s {
-                Some(s) => Ok(s),
-                None => Err(DecoderError::ExpectedError("String".to_string(), "None".to_string()))
-            },
-            None => Err(DecoderError::ExpectedError("String".to_string(), "Not found".to_string()))
-        }
+        self.pop_str("String")
     }
 
     fn read_enum<T, F>(&mut self, _name: &str, f: F) -> DecodeResult<T> where
@@ -160,9 +173,21 @@ impl rustc_serialize::Decoder for Decoder {
         self.read_enum_variant_arg(idx, f)
     }
 
-    fn read_struct<T, F>(&mut self, _name: &str, _len: usize, f: F) -> DecodeResult<T> where
+    fn read_struct<T, F>(&mut self, name: &str, _len: usize, f: F) -> DecodeResult<T> where
         F: FnOnce(&mut Decoder) -> DecodeResult<T>,
     {
+        if name == "CreatedAt" || name == "UpdatedAt" {
+            // objects saved before the field was added have no value
+            if let Some(v) = self.stack.last_mut() {
+                if v.is_none() {
+                    *v = Some(b"0".to_vec());
+                }
+            }
+            let status = replace(&mut self.status, DecoderStatus::Timestamp);
+            let r = f(self);
+            self.status = status;
+            return r;
+        }
         f(self)
     }
 
@@ -173,7 +198,7 @@ impl rustc_serialize::Decoder for Decoder {
                                -> DecodeResult<T> where
         F: FnOnce(&mut Decoder) -> DecodeResult<T>,
     {
-        if self.status != DecoderStatus::Reference {
+        if self.status != DecoderStatus::Reference && self.status != DecoderStatus::Timestamp {
             match self.properties.remove(name) {
                 Some(v) => self.stack.push(Some(v)),
                 None => {
@@ -233,10 +258,18 @@ impl rustc_serialize::Decoder for Decoder {
         f(self, opt)
     }
 
-    fn read_seq<T, F>(&mut self, _f: F) -> DecodeResult<T> where
+    fn read_seq<T, F>(&mut self, f: F) -> DecodeResult<T> where
         F: FnOnce(&mut Decoder, usize) -> DecodeResult<T>,
     {
-        Err(DecoderError::NotImplementedYet)
+        // Only sequences of bytes are supported, they are stored raw as
+        // a single attribute.
+        let bytes = try!(self.pop_bytes("Bytes"));
+        let len = bytes.len();
+        let status = replace(&mut self.status, DecoderStatus::Bytes);
+        self.bytes = bytes.into_iter();
+        let r = f(self, len);
+        self.status = status;
+        r
     }
 
     fn read_seq_elt<T, F>(&mut self, _idx: usize, f: F) -> DecodeResult<T> where
@@ -10,6 +10,8 @@ enum EncoderStatus {
     Normal,
     Id,
     Reference(String),
+    Bytes,
+    Timestamp,
 }
 
 #[derive(Debug, Clone)]
@@ -17,7 +19,7 @@ pub struct Encoder {
     pub id: usize,
     pub id_field: String,
     pub features: HashMap<String, String>,
-    pub attributes: Vec<String>,
+    pub attributes: Vec<Vec<u8>>,
     pub sets: HashSet<String>,
     pub lists: HashSet<String>,
     pub counters: HashSet<String>,
@@ -37,6 +39,12 @@ impl Encoder {
             status: EncoderStatus::Normal,
         }
     }
+
+    /// Removes the last attribute, which must be a field name.
+    fn pop_field_name(&mut self) -> EncodeResult<String> {
+        let name = try!(self.attributes.pop().ok_or(EncoderError::MissingField));
+        String::from_utf8(name).map_err(|_| EncoderError::MissingField)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -60,11 +68,17 @@ impl From<msgpack::encode::serialize::Error> for EncoderError {
     }
 }
 
+impl From<msgpack::encode::ValueWriteError> for EncoderError {
+    fn from(_: msgpack::encode::ValueWriteError) -> EncoderError {
+        EncoderError::MsgPackError
+    }
+}
+
 pub type EncodeResult<T> = Result<T, EncoderError>;
 
 macro_rules! emit_fmt {
     ($enc: ident, $e: expr) => {{
-        $enc.attributes.push(format!("{}", $e));
+        $enc.attributes.push(format!("{}", $e).into_bytes());
         Ok(())
     }}
 }
@@ -80,7 +94,7 @@ impl rustc_serialize::Encoder for Encoder {
     fn emit_usize(&mut self, v: usize) -> EncodeResult<()> {
         let s = format!("{}", v);
         match self.status {
-            EncoderStatus::Normal => self.attributes.push(s),
+            EncoderStatus::Normal | EncoderStatus::Bytes | EncoderStatus::Timestamp => self.attributes.push(s.into_bytes()),
             EncoderStatus::Id => {
                 if s != "0" {
                     self.features.insert(self.id_field.clone(), s);
@@ -89,8 +103,8 @@ impl rustc_serialize::Encoder for Encoder {
             }
             EncoderStatus::Reference(ref field) => {
                 self.attributes.pop();
-                self.attributes.push(format!("{}_id", &*field.to_ascii_lowercase()));
-                self.attributes.push(s);
+                self.attributes.push(format!("{}_id", &*field.to_ascii_lowercase()).into_bytes());
+                self.attributes.push(s.into_bytes());
             }
         }
         self.status = EncoderStatus::Normal;
@@ -100,7 +114,14 @@ impl rustc_serialize::Encoder for Encoder {
     fn emit_u64(&mut self, v: u64) -> EncodeResult<()> { emit_fmt!(self, v) }
     fn emit_u32(&mut self, v: u32) -> EncodeResult<()> { emit_fmt!(self, v) }
     fn emit_u16(&mut self, v: u16) -> EncodeResult<()> { emit_fmt!(self, v) }
-    fn emit_u8(&mut self, v: u8) -> EncodeResult<()> { emit_fmt!(self, v) }
+    fn emit_u8(&mut self, v: u8) -> EncodeResult<()> {
+        if self.status == EncoderStatus::Bytes {
+            // inside a byte sequence, append the raw value to the attribute
+            self.attributes.last_mut().unwrap().push(v);
+            return Ok(());
+        }
+        emit_fmt!(self, v)
+    }
 
     fn emit_isize(&mut self, v: isize) -> EncodeResult<()> { emit_fmt!(self, v) }
     fn emit_i64(&mut self, v: i64) -> EncodeResult<()> { emit_fmt!(self, v) }
@@ -165,11 +186,19 @@ impl rustc_serialize::Encoder for Encoder {
     {
         if self.features.contains_key("name") {
             match name {
-                "Reference" => self.status = EncoderStatus::Reference(try!(self.attributes.pop().ok_or(EncoderError::MissingField))),
-                "Counter" => { self.counters.insert(try!(self.attributes.pop().ok_or(EncoderError::MissingField))); },
-                "Set" => { self.sets.insert(try!(self.attributes.pop().ok_or(EncoderError::MissingField))); },
-                "List" => { self.lists.insert(try!(self.attributes.pop().ok_or(EncoderError::MissingField))); },
+                "Reference" => self.status = EncoderStatus::Reference(try!(self.pop_field_name())),
+                "Counter" => { let field = try!(self.pop_field_name()); self.counters.insert(field); },
+                "Set" => { let field = try!(self.pop_field_name()); self.sets.insert(field); },
+                "List" => { let field = try!(self.pop_field_name()); self.lists.insert(field); },
                 "Collection" => { try!(self.attributes.pop().ok_or(EncoderError::MissingField)); },
+                "CreatedAt" | "UpdatedAt" => {
+                    // the field name stays as an attribute, the value is
+                    // replaced by the server when saving
+                    let field = try!(self.pop_field_name());
+                    self.attributes.push(field.as_bytes().to_vec());
+                    self.features.insert(if name == "CreatedAt" { "created_at" } else { "updated_at" }.to_string(), field);
+                    self.status = EncoderStatus::Timestamp;
+                },
                 _ => return Err(EncoderError::UnknownStruct(name.to_string())),
             }
             f(self)
@@ -184,8 +213,10 @@ impl rustc_serialize::Encoder for Encoder {
     {
         if self.status == EncoderStatus::Normal && name == self.id_field {
             self.status = EncoderStatus::Id;
+        } else if self.status == EncoderStatus::Timestamp {
+            self.status = EncoderStatus::Normal;
         } else {
-            self.attributes.push(name.to_string());
+            self.attributes.push(name.as_bytes().to_vec());
         }
         f(self)
     }
@@ -229,16 +260,28 @@ impl rustc_serialize::Encoder for Encoder {
         f(self)
     }
 
-    fn emit_seq<F>(&mut self, _: usize, _: F) -> EncodeResult<()> where
+    fn emit_seq<F>(&mut self, len: usize, f: F) -> EncodeResult<()> where
         F: FnOnce(&mut Encoder) -> EncodeResult<()>,
     {
-        Err(EncoderError::NotImplementedYet)
+        // Only sequences of bytes are supported, they are stored raw as
+        // a single attribute.
+        self.attributes.push(Vec::with_capacity(len));
+        self.status = EncoderStatus::Bytes;
+        let r = f(self);
+        self.status = EncoderStatus::Normal;
+        r
     }
 
-    fn emit_seq_elt<F>(&mut self, _: usize, _: F) -> EncodeResult<()> where
+    fn emit_seq_elt<F>(&mut self, _: usize, f: F) -> EncodeResult<()> where
         F: FnOnce(&mut Encoder) -> EncodeResult<()>,
     {
-        Err(EncoderError::NotImplementedYet)
+        let len = self.attributes.len();
+        try!(f(self));
+        if self.attributes.len() != len {
+            // the element was not a byte
+            return Err(EncoderError::NotImplementedYet);
+        }
+        Ok(())
     }
 
     fn emit_map<F>(&mut self, _: usize, _: F) -> EncodeResult<()> where
@@ -265,3 +308,15 @@ pub fn msgpack_encode<T: rustc_serialize::Encodable>(t: &T) -> Result<Vec<u8>, E
     try!(t.encode(&mut msgpack::Encoder::new(&mut buf)));
     Ok(buf)
 }
+
+/// Encodes a list of binary values as a MessagePack array of raw strings.
+/// Unlike `msgpack_encode`, the values do not need to be valid UTF-8.
+pub fn msgpack_encode_bytes(values: &[Vec<u8>]) -> Result<Vec<u8>, EncoderError> {
+    let mut buf = Vec::new();
+    try!(msgpack::encode::write_array_len(&mut buf, values.len() as u32));
+    for value in values.iter() {
+        try!(msgpack::encode::write_str_len(&mut buf, value.len() as u32));
+        buf.extend(value.iter().cloned());
+    }
+    Ok(buf)
+}
@@ -67,12 +67,14 @@ extern crate redis;
 extern crate rustc_serialize;
 extern crate regex;
 extern crate stal;
+extern crate unicode_normalization;
 
 use std::ascii::AsciiExt;
 use std::collections::{HashSet, HashMap};
 use std::marker::PhantomData;
 use std::mem::replace;
 use std::string::FromUtf8Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use redis::Commands;
 use redis::ToRedisArgs;
@@ -85,17 +87,55 @@ use encoder::*;
 mod decoder;
 use decoder::*;
 
+pub mod text;
+pub use text::Normalizer;
+
+pub mod trace;
+use trace::traced;
+
+pub mod migration;
+
+pub mod check;
+pub use check::check;
+
 mod lua;
-use lua::{DELETE, SAVE};
+use lua::{DELETE, DELETE_ALL, EXPIRE, MULTI_SORT, PAGE, PURGE, RANGE_STORE, REINDEX, SAVE, SAVE_MANY, SOFT_DELETE, UPDATE_ALL, VIA};
 
 /// Declares a struct.
 /// Fields may be declared as a part of uniques, indices, or regular fields.
+/// Regular fields are declared after the sections.
 /// Every field must have a default value.
 /// The struct will derive RustcEncodable, RustcDecodable, and Default.
 /// More `derive`s can be specified.
 ///
 /// A property `id: usize = 0;` is automatically added to track the object.
 ///
+/// Fields declared as `text` are indexed by each of their words, and can be
+/// found using `Query::search`. Declaring `stemming;` indexes the stem of
+/// each word, and `stop_words;` skips common English words.
+///
+/// Fields declared as `prefix` can be found by the beginning of their value
+/// using `Query::starts_with`.
+///
+/// Each line in `composite_uniques` lists fields whose combined values must
+/// be unique. They can be found using `with_composite`.
+///
+/// Each line in `geo` declares an index locating the object by a longitude
+/// and a latitude field, to find it using `Query::within_radius` and
+/// `Query::within_box`.
+///
+/// Each line in `composite_indices` lists fields indexed together, in a set
+/// for each combination of their values, joined by `:` with any `:` or `\`
+/// in a value escaped by a backslash. `find!` and `Query::from_keys` use it
+/// when filtering by all of those fields.
+///
+/// The `normalize` section lists the `Normalizer`s applied to the value of
+/// a field before it is used in a unique or index key, so `with` and `find!`
+/// match values that only differ in case or surrounding whitespace.
+///
+/// Declaring `soft_delete;` makes `delete` keep the object, so it can be
+/// brought back with `restore`.
+///
 /// # Examples
 /// ```
 /// # #[macro_use(model)] extern crate ohmers;
@@ -107,131 +147,254 @@ use lua::{DELETE, SAVE};
 ///         indices { my_index:u8 = 0; };
 ///         other_field:String = "".to_string();
 ///     });
+/// model!(
+///     MyUser {
+///         uniques { email:String = "".to_string(); };
+///         indices { nickname:String = "".to_string(); };
+///         normalize { email: Lowercase, Trim; nickname: Nfkc, Custom(str::to_uppercase); };
+///     });
+/// model!(
+///     MyAccount {
+///         composite_uniques { tenant_id, email; };
+///         composite_indices { tenant_id, role; };
+///         tenant_id:usize = 0;
+///         email:String = "".to_string();
+///         role:String = "".to_string();
+///     });
+/// model!(
+///     MyPlace {
+///         geo { location: longitude, latitude; };
+///         longitude:f64 = 0.0;
+///         latitude:f64 = 0.0;
+///     });
+/// model!(
+///     MyDeletableStruct {
+///         soft_delete;
+///         some_field:String = "".to_string();
+///     });
+/// model!(
+///     MySearchableStruct {
+///         stemming;
+///         stop_words;
+///         text { description:String = "".to_string(); };
+///         prefix { title:String = "".to_string(); };
+///     });
 /// # fn main() {
 /// # }
 /// ```
 #[macro_export]
 macro_rules! model {
-    ($class: ident { $($key: ident:$proptype: ty = $default: expr);*; } ) => {
-        model!(
-                $class {
-                    uniques { };
-                    indices { };
-                    $($key:$proptype = $default;)*
+    // Sections are parsed one at a time, accumulating the text and prefix
+    // fields, uniques, indices, and extra `Ohmer` methods.
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     text { $($nkey: ident:$nproptype: ty = $ndefault: expr;)* };
+     $($rest: tt)*
+     ) => {
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)* $($nkey:$nproptype = $ndefault;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)*
+                fn text_fields<'a>(&self) -> ::std::collections::HashSet<&'a str> {
+                    let mut hs = ::std::collections::HashSet::new();
+                    $(
+                        hs.insert(stringify!($nkey));
+                    )*
+                    hs
                 }
-                );
+                ]
+                $($rest)*);
     };
-    (
-     derive { $($derive: ident),* }
-     $class: ident { $($key: ident:$proptype: ty = $default: expr);*; } ) => {
-        model!(
-                derive { $($derive),* }
-                $class {
-                    uniques { };
-                    indices { };
-                    $($key:$proptype = $default;)*
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     prefix { $($pkey: ident:$pproptype: ty = $pdefault: expr;)* };
+     $($rest: tt)*
+     ) => {
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)* $($pkey:$pproptype = $pdefault;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)*
+                fn prefix_fields<'a>(&self) -> ::std::collections::HashSet<&'a str> {
+                    let mut hs = ::std::collections::HashSet::new();
+                    $(
+                        hs.insert(stringify!($pkey));
+                    )*
+                    hs
                 }
-                );
+                ]
+                $($rest)*);
     };
-    ($class: ident {
-     uniques { $($ukey: ident:$uproptype: ty = $udefault: expr;)* };
-     $($key: ident:$proptype: ty = $default: expr;)* }
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     composite_uniques { $($($cfield: ident),+;)* };
+     $($rest: tt)*
      ) => {
-        model!(
-                $class {
-                    uniques {
-                        $(
-                            $ukey: $uproptype = $udefault;
-                        )*
-                    };
-                    indices { };
-                    $($key:$proptype = $default;)*
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)*
+                fn composite_unique_fields<'a>(&self) -> Vec<Vec<&'a str>> {
+                    vec![$(vec![$(stringify!($cfield)),+]),*]
                 }
-                );
+                ]
+                $($rest)*);
     };
-    (
-     derive { $($derive: ident),* }
-     $class: ident {
-     uniques { $($ukey: ident:$uproptype: ty = $udefault: expr;)* };
-     $($key: ident:$proptype: ty = $default: expr;)* }
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     geo { $($gname: ident: $glon: ident, $glat: ident;)* };
+     $($rest: tt)*
      ) => {
-        model!(
-                derive { $($derive),* }
-                $class {
-                    uniques {
-                        $(
-                            $ukey: $uproptype = $udefault;
-                        )*
-                    };
-                    indices { };
-                    $($key:$proptype = $default;)*
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)*
+                fn geo_fields<'a>(&self) -> Vec<(&'a str, &'a str, &'a str)> {
+                    vec![$((stringify!($gname), stringify!($glon), stringify!($glat))),*]
                 }
-                );
+                ]
+                $($rest)*);
     };
-    ($class: ident {
-     indices { $($ikey: ident:$iproptype: ty = $idefault: expr;)* };
-     $($key: ident:$proptype: ty = $default: expr;)* }
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     composite_indices { $($($cfield: ident),+;)* };
+     $($rest: tt)*
      ) => {
-        model!(
-                $class {
-                    uniques { };
-                    indices {
-                        $(
-                            $ikey: $iproptype = $idefault;
-                        )*
-                    };
-                    $($key:$proptype = $default;)*
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)*
+                fn composite_index_fields<'a>(&self) -> Vec<Vec<&'a str>> {
+                    vec![$(vec![$(stringify!($cfield)),+]),*]
                 }
-                );
+                ]
+                $($rest)*);
     };
-    (
-     derive { $($derive: ident),* }
-     $class: ident {
-     indices { $($ikey: ident:$iproptype: ty = $idefault: expr;)* };
-     $($key: ident:$proptype: ty = $default: expr;)* }
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     normalize { $($nfield: ident: $($normalizer: ident $(($arg: expr))*),*;)* };
+     $($rest: tt)*
      ) => {
-        model!(
-                derive { $($derive),* }
-                $class {
-                    uniques { };
-                    indices {
-                        $(
-                            $ikey: $iproptype = $idefault;
-                        )*
-                    };
-                    $($key:$proptype = $default;)*
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)*
+                fn normalizers(&self, field: &str) -> Vec<::ohmers::Normalizer> {
+                    $(
+                        if field == stringify!($nfield) {
+                            return vec![$(::ohmers::Normalizer::$normalizer $(($arg))*),*];
+                        }
+                    )*
+                    vec![]
                 }
-                );
+                ]
+                $($rest)*);
     };
-    (
-     $class: ident {
-     uniques { $($ukey: ident:$uproptype: ty = $udefault: expr;)* };
-     indices { $($ikey: ident:$iproptype: ty = $idefault: expr;)* };
-     $($key: ident:$proptype: ty = $default: expr;)* }
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     stemming;
+     $($rest: tt)*
      ) => {
-        model!(
-                derive { }
-                $class {
-                    uniques {
-                        $(
-                            $ukey: $uproptype = $udefault;
-                        )*
-                    };
-                    indices {
-                        $(
-                            $ikey: $iproptype = $idefault;
-                        )*
-                    };
-                    $($key:$proptype = $default;)*
-                }
-                );
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)* fn stemming(&self) -> bool { true }]
+                $($rest)*);
     };
-    (
-     derive { $($derive: ident),* }
-     $class: ident {
-     uniques { $($ukey: ident:$uproptype: ty = $udefault: expr;)* };
-     indices { $($ikey: ident:$iproptype: ty = $idefault: expr;)* };
-     $($key: ident:$proptype: ty = $default: expr;)* }
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     stop_words;
+     $($rest: tt)*
+     ) => {
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)* fn stop_words(&self) -> &'static [&'static str] { ::ohmers::text::STOP_WORDS }]
+                $($rest)*);
+    };
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     uniques { $($nkey: ident:$nproptype: ty = $ndefault: expr;)* };
+     $($rest: tt)*
+     ) => {
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)* $($nkey:$nproptype = $ndefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)*]
+                $($rest)*);
+    };
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     indices { $($nkey: ident:$nproptype: ty = $ndefault: expr;)* };
+     $($rest: tt)*
+     ) => {
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)* $($nkey:$nproptype = $ndefault;)*]
+                [$($item)*]
+                $($rest)*);
+    };
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     soft_delete;
+     $($rest: tt)*
+     ) => {
+        model!(@parse [$($derive),*] $class
+                [$($key:$proptype = $default;)*]
+                [$($ukey:$uproptype = $udefault;)*]
+                [$($ikey:$iproptype = $idefault;)*]
+                [$($item)* fn soft_delete(&self) -> bool { true }]
+                $($rest)*);
+    };
+    // The regular fields follow the sections, and are matched at once.
+    (@parse [$($derive: ident),*] $class: ident
+     [$($key: ident:$proptype: ty = $default: expr;)*]
+     [$($ukey: ident:$uproptype: ty = $udefault: expr;)*]
+     [$($ikey: ident:$iproptype: ty = $idefault: expr;)*]
+     [$($item: tt)*]
+     $($fkey: ident:$fproptype: ty = $fdefault: expr;)*
      ) => {
         #[derive(RustcEncodable, RustcDecodable, Debug, $($derive,)* )]
         struct $class {
@@ -239,6 +402,9 @@ macro_rules! model {
             $(
                 $key: $proptype,
             )*
+            $(
+                $fkey: $fproptype,
+            )*
             $(
                 $ukey: $uproptype,
             )*
@@ -254,6 +420,9 @@ macro_rules! model {
                     $(
                         $key: $default,
                     )*
+                    $(
+                        $fkey: $fdefault,
+                    )*
                     $(
                         $ukey: $udefault,
                     )*
@@ -282,6 +451,18 @@ macro_rules! model {
                 format!("{}:indices:{}:{}", stringify!($class), field, value)
             }
 
+            fn key_for_sorted(&self, field: &str) -> String {
+                format!("{}:sorted:{}", stringify!($class), field)
+            }
+
+            fn key_for_prefix(&self, field: &str) -> String {
+                format!("{}:prefix:{}", stringify!($class), field)
+            }
+
+            fn key_for_geo(&self, name: &str) -> String {
+                format!("{}:geo:{}", stringify!($class), name)
+            }
+
             fn unique_fields<'a>(&self) -> ::std::collections::HashSet<&'a str> {
                 #![allow(unused_mut)]
                 let mut hs = ::std::collections::HashSet::new();
@@ -299,6 +480,8 @@ macro_rules! model {
                 )*
                 hs
             }
+
+            $($item)*
         }
 
         impl PartialEq for $class {
@@ -306,7 +489,16 @@ macro_rules! model {
                 self.id == other.id
             }
         }
-    }
+    };
+    (
+     derive { $($derive: ident),* }
+     $class: ident { $($body: tt)* }
+     ) => {
+        model!(@parse [$($derive),*] $class [] [] [] [] $($body)*);
+    };
+    ($class: ident { $($body: tt)* }) => {
+        model!(derive { } $class { $($body)* });
+    };
 }
 
 /// Creates a new instance of `$class` using the default properties,
@@ -376,7 +568,8 @@ macro_rules! create {
 }
 
 /// Returns a `Query` with all the `$class` objects  where `$key` is `$value`.
-/// All the `$key` must be declared as `indices` in the `model!` declaration.
+/// All the `$key` must be declared as `indices` in the `model!` declaration,
+/// or be part of a `composite_indices` group whose fields are all present.
 ///
 /// # Examples
 ///
@@ -423,19 +616,120 @@ macro_rules! find {
         ::ohmers::Query::<$class>::new(
                 ::ohmers::StalSet::Union(vec![
                     $(
-                    ::ohmers::StalSet::Inter(
-                        vec![
+                    ::ohmers::Query::<$class>::keys(&[
                         $(
-                            ::ohmers::Query::<$class>::key(stringify!($key), &*format!("{}", $value)),
+                            (stringify!($key), &*format!("{}", $value)),
                         )*
-                        ]
-                    ),
+                        ]),
                     )*
                     ]
                 ), &$conn)
     }}
 }
 
+/// Returns a `Query` with all the `$class` objects matching a boolean
+/// expression, or an error if a relation set belongs to an object that was
+/// not saved.
+///
+/// Each clause is either a list of `{ $key: $value, }` pairs, as in `find!`,
+/// or a `Set` property of another object, like `event.participants`.
+/// Clauses can be negated with `!` or `not`, combined with `&&` and `||`,
+/// and grouped with parentheses. `&&` has precedence over `||`.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create, filter, insert)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::{Ohmer, Set};
+/// # use redis::Commands;
+/// model!(
+///     Guest {
+///         indices {
+///             diet:String = "".to_string();
+///             age:u8 = 0;
+///         };
+///     });
+///
+/// model!(
+///     Wedding {
+///         guests:Set<Guest> = Set::new();
+///     });
+///
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// # let _:bool = client.del("Guest:indices:diet:vegan").unwrap();
+/// # let _:bool = client.del("Guest:indices:age:8").unwrap();
+/// let ann = create!(Guest { diet: "vegan".to_string(), age: 30, }, &client).unwrap();
+/// let bob = create!(Guest { diet: "vegan".to_string(), age: 8, }, &client).unwrap();
+/// let cid = create!(Guest { diet: "celiac".to_string(), age: 40, }, &client).unwrap();
+/// let wedding = create!(Wedding {}, &client).unwrap();
+/// insert!(wedding.guests, ann, &client).unwrap();
+/// insert!(wedding.guests, bob, &client).unwrap();
+/// insert!(wedding.guests, cid, &client).unwrap();
+///
+/// let query = filter!(Guest
+///     wedding.guests && ({ diet: "vegan", } || { diet: "celiac", }) && !{ age: 8, },
+///     &client).unwrap();
+/// assert_eq!(query.ids().unwrap(), vec![ann.id, cid.id]);
+/// # }
+/// ```
+#[macro_export]
+macro_rules! filter {
+    // The expression is parsed one clause at a time, accumulating the
+    // intersections already joined by `||` and the clauses of the current
+    // intersection.
+    (@or $class: ident [$($or: expr),*] [$($and: expr),*] , $conn: expr) => {{
+        let set = (|| -> Result<::ohmers::StalSet, ::ohmers::OhmerError> {
+            Ok(filter!(@or $class [$($or),*] [$($and),*]))
+        })();
+        set.map(|set| ::ohmers::Query::<$class>::new(set, &$conn))
+    }};
+    (@or $class: ident [$($or: expr),*] [$($and: expr),*]) => {
+        ::ohmers::StalSet::Union(vec![$($or,)* ::ohmers::StalSet::Inter(vec![$($and),*])])
+    };
+    (@or $class: ident [$($or: expr),*] [$($and: expr),*] || $($rest: tt)*) => {
+        filter!(@or $class [$($or,)* ::ohmers::StalSet::Inter(vec![$($and),*])] [] $($rest)*)
+    };
+    (@or $class: ident [$($or: expr),*] [$($and: expr),*] && $($rest: tt)*) => {
+        filter!(@or $class [$($or),*] [$($and),*] $($rest)*)
+    };
+    (@or $class: ident [$($or: expr),*] [$($and: expr),*] ! $($rest: tt)*) => {
+        filter!(@not $class [$($or),*] [$($and),*] $($rest)*)
+    };
+    (@or $class: ident [$($or: expr),*] [$($and: expr),*] not $($rest: tt)*) => {
+        filter!(@not $class [$($or),*] [$($and),*] $($rest)*)
+    };
+    (@or $class: ident [$($or: expr),*] [$($and: expr),*] $clause: tt $($rest: tt)*) => {
+        filter!(@clause $class [$($or),*] [$($and),*] [] $clause $($rest)*)
+    };
+    (@not $class: ident [$($or: expr),*] [$($and: expr),*] $clause: tt $($rest: tt)*) => {
+        filter!(@clause $class [$($or),*] [$($and),*] [::ohmers::Query::<$class>::not] $clause $($rest)*)
+    };
+    // A clause is added to the current intersection, negated if `$not` is
+    // not empty.
+    (@clause $class: ident [$($or: expr),*] [$($and: expr),*] [$($not: tt)*]
+     ( $($inner: tt)* ) $($rest: tt)*) => {
+        filter!(@or $class [$($or),*] [$($and,)* $($not)*(filter!(@or $class [] [] $($inner)*))] $($rest)*)
+    };
+    (@clause $class: ident [$($or: expr),*] [$($and: expr),*] [$($not: tt)*]
+     { $($key: ident: $value: expr),* $(,)* } $($rest: tt)*) => {
+        filter!(@or $class [$($or),*] [$($and,)* $($not)*(::ohmers::Query::<$class>::keys(&[
+                $(
+                    (stringify!($key), &*format!("{}", $value)),
+                )*
+                ]))] $($rest)*)
+    };
+    (@clause $class: ident [$($or: expr),*] [$($and: expr),*] [$($not: tt)*]
+     $obj: ident . $prop: ident $($rest: tt)*) => {
+        filter!(@or $class [$($or),*] [$($and,)* $($not)*(try!($obj.$prop.key(stringify!($prop), &$obj)))] $($rest)*)
+    };
+    ($class: ident $($rest: tt)*) => {
+        filter!(@or $class [] [] $($rest)*)
+    };
+}
+
 /// Properties declared as `Collection` can use the collection macro to get a
 /// `Query` to iterate over all of its elements.
 /// A `Collection` is an accessor to objects that have a `Reference` to the
@@ -538,6 +832,24 @@ macro_rules! try_iter {
     }}
 }
 
+/// Creates an iterable of `$n` random elements in `$obj.$prop`, without
+/// repetitions. The property must be a Set.
+#[macro_export]
+macro_rules! random {
+    ($obj: ident.$prop: ident, $n: expr, $conn: expr) => {{
+        $obj.$prop.random(stringify!($prop), &$obj, $n, false, &$conn)
+    }}
+}
+
+/// Retrieves and removes a random element from `$obj.$prop`.
+/// The property must be a Set.
+#[macro_export]
+macro_rules! pop_random {
+    ($obj: ident.$prop: ident, $conn: expr) => {{
+        $obj.$prop.pop_random(stringify!($prop), &$obj, &$conn)
+    }}
+}
+
 /// Checks if an element is in a List or a Set.
 #[macro_export]
 macro_rules! contains {
@@ -581,10 +893,70 @@ macro_rules! remove {
 /// assert_eq!(ohmers::with::<OperativeSystem, _>("name", "OS X", &client).unwrap().unwrap().major_version, 10);
 /// # }
 /// ```
-pub fn with<T: Ohmer, S: ToRedisArgs>(property: &str, value: S, r: &redis::Client) -> Result<Option<T>, DecoderError> {
+pub fn with<'a, T: Ohmer, S: ToRedisArgs>(property: &str, value: S, r: impl Connection<'a>) -> Result<Option<T>, DecoderError> {
+    let r = r.connection();
     let mut obj = T::default();
 
-    let opt_id:Option<usize> = try!(r.hget(format!("{}:uniques:{}", obj.get_class_name(), property), value));
+    let mut args = value.to_redis_args();
+    if args.len() == 1 {
+        if let Ok(value) = String::from_utf8(args[0].clone()) {
+            args[0] = obj.normalize(property, &*value).into_bytes();
+        }
+    }
+    let opt_id:Option<usize> = try!(traced(r).hget(format!("{}:uniques:{}", obj.get_class_name(), property), args));
+
+    let id = match opt_id {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    try!(obj.load(id, r));
+    Ok(Some(obj))
+}
+
+/// Finds an element by a composite unique index, given the value of each of
+/// its fields.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::Ohmer;
+/// # use redis::Commands;
+/// model!(
+///     Seat {
+///         composite_uniques { row, number; };
+///         row:String = "".to_string();
+///         number:u8 = 0;
+///     });
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// # let _:bool = client.del("Seat:uniques:row+number").unwrap();
+/// let seat = create!(Seat { row: "F".to_owned(), number: 12, }, &client).unwrap();
+/// assert!(create!(Seat { row: "F".to_owned(), number: 12, }, &client).is_err());
+/// assert_eq!(ohmers::with_composite::<Seat>(&[("number", "12"), ("row", "F")], &client).unwrap().unwrap().id, seat.id);
+/// # }
+/// ```
+pub fn with_composite<'a, T: Ohmer>(values: &[(&str, &str)], r: impl Connection<'a>) -> Result<Option<T>, DecoderError> {
+    let r = r.connection();
+    let mut obj = T::default();
+
+    // use the order of the fields in the declaration
+    let mut fields = values.iter().map(|&(field, _)| field).collect::<Vec<_>>();
+    for group in obj.composite_unique_fields() {
+        if group.len() == fields.len() && group.iter().all(|field| fields.contains(field)) {
+            fields = group;
+            break;
+        }
+    }
+    let values = values.iter().map(|&(field, value)| (field.to_string(), obj.normalize(field, value))).collect();
+    let value = match composite_value(&*fields, &values) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    let opt_id:Option<usize> = try!(traced(r).hget(format!("{}:uniques:{}", obj.get_class_name(), fields.join("+")), value));
 
     let id = match opt_id {
         Some(id) => id,
@@ -614,12 +986,102 @@ pub fn with<T: Ohmer, S: ToRedisArgs>(property: &str, value: S, r: &redis::Clien
 /// assert_eq!(&*ohmers::get::<Server>(server.id, &client).unwrap().name, "My Server");
 /// # }
 /// ```
-pub fn get<T: Ohmer>(id: usize, r: &redis::Client) -> Result<T, DecoderError> {
+pub fn get<'a, T: Ohmer>(id: usize, r: impl Connection<'a>) -> Result<T, DecoderError> {
+    let r = r.connection();
     let mut obj = T::default();
     try!(obj.load(id, r));
     Ok(obj)
 }
 
+/// Gets an element by id, loading only `fields`. The other fields keep
+/// their default value.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::Ohmer;
+/// model!(
+///     Post {
+///         title:String = "".to_string();
+///         body:String = "".to_string();
+///     });
+///
+/// #[derive(RustcDecodable)]
+/// struct PostTitle {
+///     id: usize,
+///     title: String,
+/// }
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let post = create!(Post { title: "Hello".to_owned(), body: "A long text".to_owned(), }, &client).unwrap();
+/// let partial = ohmers::get_fields::<Post>(post.id, &["title"], &client).unwrap();
+/// assert_eq!(&*partial.title, "Hello");
+/// assert_eq!(&*partial.body, "");
+/// let projection = ohmers::get_fields_as::<Post, PostTitle>(post.id, &["title"], &client).unwrap();
+/// assert_eq!(projection.id, post.id);
+/// assert_eq!(&*projection.title, "Hello");
+/// # }
+/// ```
+pub fn get_fields<'a, T: Ohmer>(id: usize, fields: &[&str], r: impl Connection<'a>) -> Result<T, OhmerError> {
+    let r = r.connection();
+    let default = T::default();
+    let encoder = try!(default.encoder());
+    let mut properties = HashMap::new();
+    for pair in encoder.attributes.chunks(2) {
+        properties.insert(try!(String::from_utf8(pair[0].clone())), pair[1].clone());
+    }
+    for field in fields.iter() {
+        // selected fields without a stored value are missing
+        properties.remove(*field);
+        properties.remove(&*format!("{}_id", field.to_ascii_lowercase()));
+    }
+    properties.extend(try!(load_fields(&default, id, fields, r)));
+
+    let mut decoder = Decoder::new(properties);
+    Ok(try!(rustc_serialize::Decodable::decode(&mut decoder)))
+}
+
+/// Gets the values of `fields` of an element by id, decoded as a
+/// projection struct `P`. `P` may have an `id` field.
+pub fn get_fields_as<'a, T: Ohmer, P: rustc_serialize::Decodable>(id: usize, fields: &[&str], r: impl Connection<'a>) -> Result<P, OhmerError> {
+    let r = r.connection();
+    let properties = try!(load_fields(&T::default(), id, fields, r));
+    let mut decoder = Decoder::new(properties);
+    Ok(try!(rustc_serialize::Decodable::decode(&mut decoder)))
+}
+
+/// Gets the stored values of `fields` using HMGET, including references.
+fn load_fields<T: Ohmer>(obj: &T, id: usize, fields: &[&str], r: &redis::ConnectionLike) -> Result<HashMap<String, Vec<u8>>, OhmerError> {
+    let names = field_names(fields);
+    let values:Vec<Option<Vec<u8>>> = try!(redis::cmd("HMGET")
+            .arg(format!("{}:{}", obj.get_class_name(), id))
+            .arg(&*names)
+            .query(&traced(r)));
+    Ok(field_properties(id, &names, values))
+}
+
+/// Hash keys requested for `fields`, with the key of each reference.
+fn field_names(fields: &[&str]) -> Vec<String> {
+    let mut names = vec![];
+    for field in fields.iter() {
+        names.push(field.to_string());
+        names.push(format!("{}_id", field.to_ascii_lowercase()));
+    }
+    names
+}
+
+/// Properties of object `id` from the reply of HMGET with `names`.
+fn field_properties(id: usize, names: &[String], values: Vec<Option<Vec<u8>>>) -> HashMap<String, Vec<u8>> {
+    let mut properties:HashMap<String, Vec<u8>> = names.iter().cloned().zip(values.into_iter())
+        .filter_map(|(name, value)| value.map(|v| (name, v)))
+        .collect();
+    properties.insert("id".to_string(), format!("{}", id).into_bytes());
+    properties
+}
+
 /// Gets a query for all elements.
 ///
 /// # Examples
@@ -648,7 +1110,8 @@ pub fn get<T: Ohmer>(id: usize, r: &redis::Client) -> Result<T, DecoderError> {
 ///     ]);
 /// # }
 /// ```
-pub fn all_query<'a, T: 'a + Ohmer>(r: &'a redis::Client) -> Result<Query<'a, T>, OhmerError> {
+pub fn all_query<'a, T: 'a + Ohmer>(r: impl Connection<'a>) -> Result<Query<'a, T>, OhmerError> {
+    let r = r.connection();
     let class_name = T::default().get_class_name();
     Ok(Query::<'a, T>::new(stal::Set::Key(format!("{}:all", class_name).as_bytes().to_vec()), r))
 }
@@ -680,10 +1143,331 @@ pub fn all_query<'a, T: 'a + Ohmer>(r: &'a redis::Client) -> Result<Query<'a, T>
 ///     ]);
 /// # }
 /// ```
-pub fn all<'a, T: 'a + Ohmer>(r: &'a redis::Client) -> Result<Iter<T>, OhmerError> {
+pub fn all<'a, T: 'a + Ohmer>(r: impl Connection<'a>) -> Result<Iter<'a, T>, OhmerError> {
+    let r = r.connection();
     Ok(try!(try!(all_query(r)).try_iter()))
 }
 
+/// Gets an iterator for all elements that walks `Class:all` with SSCAN,
+/// loading `batch` objects at a time. Unlike `all`, the ids are not
+/// loaded at once, so objects added or removed while iterating may or may
+/// not be returned, and an object may be returned more than once.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::Ohmer;
+/// # use redis::Commands;
+/// model!(
+///     Reading {
+///         celsius:i16 = 0;
+///     });
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// # let _:bool = client.del("Reading:all").unwrap();
+/// for celsius in 0..25 {
+///     create!(Reading { celsius: celsius, }, &client).unwrap();
+/// }
+/// let total = ohmers::scan::<Reading>(10, &client).unwrap().map(|r| r.unwrap().celsius).fold(0, |a, b| a + b);
+/// assert_eq!(total, 300);
+/// # }
+/// ```
+pub fn scan<'a, T: 'a + Ohmer>(batch: usize, r: impl Connection<'a>) -> Result<Scan<'a, T>, OhmerError> {
+    let r = r.connection();
+    let class_name = T::default().get_class_name();
+    Scan::new(format!("{}:all", class_name).into_bytes(), batch, r)
+}
+
+/// Brings back an object deleted while `soft_delete` was enabled, adding it
+/// again to the indices and uniques. Returns `None` if the object was not
+/// deleted.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::Ohmer;
+/// # use redis::Commands;
+/// model!(
+///     Document {
+///         soft_delete;
+///         uniques { path:String = "".to_string(); };
+///     });
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// # let _:bool = client.del("Document:uniques:path").unwrap();
+/// let document = create!(Document { path: "/tmp/notes.txt".to_string(), }, &client).unwrap();
+/// let id = document.id;
+/// document.delete(&client).unwrap();
+/// assert!(ohmers::with::<Document, _>("path", "/tmp/notes.txt", &client).unwrap().is_none());
+/// ohmers::restore::<Document>(id, &client).unwrap().unwrap();
+/// assert!(ohmers::with::<Document, _>("path", "/tmp/notes.txt", &client).unwrap().is_some());
+/// # }
+/// ```
+pub fn restore<'a, T: Ohmer>(id: usize, r: impl Connection<'a>) -> Result<Option<T>, OhmerError> {
+    let r = r.connection();
+    let class_name = T::default().get_class_name();
+    let deleted:bool = try!(traced(r).sismember(format!("{}:deleted", class_name), id));
+    if !deleted {
+        return Ok(None);
+    }
+    let mut obj:T = try!(get(id, r));
+    try!(obj.save(r));
+    Ok(Some(obj))
+}
+
+/// Gets a query for all elements deleted while `soft_delete` was enabled.
+pub fn deleted_query<'a, T: 'a + Ohmer>(r: impl Connection<'a>) -> Result<Query<'a, T>, OhmerError> {
+    let r = r.connection();
+    let class_name = T::default().get_class_name();
+    Ok(Query::<'a, T>::new(stal::Set::Key(format!("{}:deleted", class_name).as_bytes().to_vec()), r))
+}
+
+/// Removes the objects that expired from `all`, the indices and the uniques.
+/// Returns the number of objects removed.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, new)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::Ohmer;
+/// # use std::thread::sleep;
+/// # use std::time::Duration;
+/// model!(
+///     Session {
+///         uniques { token:String = "".to_string(); };
+///     });
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let mut session = new!(Session { token: "secret".to_string(), });
+/// session.save_with_ttl(1, &client).unwrap();
+/// sleep(Duration::from_millis(1100));
+/// assert!(ohmers::purge_expired::<Session>(&client).unwrap() >= 1);
+/// assert!(ohmers::with::<Session, _>("token", "secret", &client).unwrap().is_none());
+/// # }
+/// ```
+pub fn purge_expired<'a, T: Ohmer>(r: impl Connection<'a>) -> Result<usize, OhmerError> {
+    let r = r.connection();
+    let class_name = T::default().get_class_name();
+    let script = lua_script(PURGE);
+    Ok(try!(script.arg(class_name).invoke(&traced(r))))
+}
+
+/// Saves several objects in a single request, setting the `id` of the
+/// new ones. Returns the result of saving each object, in the same order.
+/// If `abort` is true, none of the objects is saved if any of them fails,
+/// and the first error is returned instead.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, new)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::{Ohmer, OhmerError};
+/// # use redis::Commands;
+/// model!(
+///     Member {
+///         uniques { handle:String = "".to_string(); };
+///     });
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// # let _:bool = client.del("Member:uniques:handle").unwrap();
+/// let mut members = vec![
+///     new!(Member { handle: "ada".to_string(), }),
+///     new!(Member { handle: "ada".to_string(), }),
+///     new!(Member { handle: "grace".to_string(), }),
+/// ];
+/// let results = ohmers::save_many(&mut members, false, &client).unwrap();
+/// assert_eq!(results[1], Err(OhmerError::UniqueIndexViolation("handle".to_string())));
+/// assert!(members[0].id > 0 && members[1].id == 0 && members[2].id > 0);
+///
+/// let mut members = vec![new!(Member { handle: "alan".to_string(), }), new!(Member { handle: "grace".to_string(), })];
+/// assert_eq!(ohmers::save_many(&mut members, true, &client).unwrap_err(),
+///     OhmerError::UniqueIndexViolation("handle".to_string()));
+/// assert_eq!(members[0].id, 0);
+/// # }
+/// ```
+pub fn save_many<'a, T: Ohmer>(objects: &mut [T], abort: bool, r: impl Connection<'a>) -> Result<Vec<Result<(), OhmerError>>, OhmerError> {
+    let r = r.connection();
+    let mut results = Vec::with_capacity(objects.len());
+    let mut encoders = vec![];
+    let mut args = vec![b"7".to_vec(), if abort { b"abort".to_vec() } else { vec![] }];
+    for obj in objects.iter() {
+        let encoded = obj.encoder().and_then(|encoder| save_args(obj, &encoder).map(|a| (encoder, a)));
+        match encoded {
+            Ok((encoder, a)) => {
+                args.extend(a);
+                encoders.push(Some(encoder));
+                results.push(Ok(()));
+            },
+            Err(e) => {
+                if abort {
+                    return Err(e);
+                }
+                encoders.push(None);
+                results.push(Err(e));
+            },
+        }
+    }
+    if args.len() == 2 {
+        return Ok(results);
+    }
+
+    let script = save_script(SAVE_MANY);
+    let saved:Vec<Vec<String>> = try!(script.arg(args).invoke(&traced(r)));
+    let mut saved = saved.into_iter();
+    for (i, obj) in objects.iter_mut().enumerate() {
+        let encoder = match encoders[i] {
+            Some(ref encoder) => encoder,
+            None => continue,
+        };
+        let result = match saved.next() {
+            Some(result) => result,
+            None => return Err(reply_error("Missing result of a saved object")),
+        };
+        if result.len() < 2 {
+            return Err(reply_error("Missing result of a saved object"));
+        }
+        let id:usize = result[0].parse().unwrap_or(0);
+        // the server time, or the error if the id is 0
+        let value = &result[1];
+        if id == 0 && value.len() == 0 {
+            // valid, but not saved because another object failed
+            continue;
+        }
+        if id == 0 {
+            let error = save_error(&*value).unwrap_or_else(|| OhmerError::RedisError(
+                    redis::RedisError::from((redis::ErrorKind::ResponseError, "Error saving object", value.clone()))));
+            if abort {
+                return Err(error);
+            }
+            results[i] = Err(error);
+            continue;
+        }
+        let time:u64 = value.parse().unwrap_or(0);
+        if time > 0 {
+            let created:u64 = match result.get(2) {
+                Some(created) => created.parse().unwrap_or(0),
+                None => return Err(reply_error("Missing creation time of a saved object")),
+            };
+            try!(stamp(obj, encoder, id, time, created));
+        }
+        obj.set_id(id);
+    }
+    Ok(results)
+}
+
+/// Rebuilds the indices, uniques, sorted sets and geo indices of every
+/// object in `Class:all` from its stored hash, `batch` objects per request,
+/// as `save` would write them. Useful after adding indices to a model, or
+/// if the indices are out of sync with the objects.
+/// Hashes and timestamps are not changed. Returns the conflicts found, as
+/// the id of the object and the error: unique values used by another
+/// object are not indexed, and objects with invalid coordinates, without
+/// a unique value or that cannot be decoded are skipped. Since the ids are
+/// read with SSCAN, an object may be reindexed, and its conflict reported,
+/// more than once.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::{Ohmer, Query};
+/// # use redis::Commands;
+/// model!(
+///     Sketch {
+///         indices { artist:String = "".to_string(); };
+///     });
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let sketch = create!(Sketch { artist: "Degas".to_string(), }, &client).unwrap();
+/// let _:bool = client.del("Sketch:indices:artist:Degas").unwrap();
+/// assert!(Query::<Sketch>::find("artist", "Degas", &client).try_iter().unwrap().next().is_none());
+///
+/// assert_eq!(ohmers::reindex::<Sketch>(100, &client).unwrap().len(), 0);
+/// assert!(Query::<Sketch>::find("artist", "Degas", &client).try_iter().unwrap().any(|s| s.id == sketch.id));
+/// # }
+/// ```
+pub fn reindex<'a, T: Ohmer>(batch: usize, r: impl Connection<'a>) -> Result<Vec<(usize, OhmerError)>, OhmerError> {
+    let r = r.connection();
+    let script = save_script(REINDEX);
+    let mut conflicts = vec![];
+    let mut scan = try!(scan::<T>(batch, r));
+    while scan.cursor.is_some() {
+        try!(scan.fetch());
+        let mut args = vec![b"7".to_vec()];
+        for (id, obj) in replace(&mut scan.objects, vec![].into_iter()) {
+            let encoded = obj.and_then(|obj| obj.encoder().and_then(|encoder| save_args(&obj, &encoder)));
+            match encoded {
+                Ok(a) => args.extend(a),
+                Err(e) => conflicts.push((id, e)),
+            }
+        }
+        if args.len() == 1 {
+            continue;
+        }
+        let found:Vec<Vec<String>> = try!(script.arg(args).invoke(&traced(r)));
+        for mut conflict in found {
+            if conflict.len() != 2 {
+                return Err(reply_error("Unexpected conflict in reindex"));
+            }
+            let message = conflict.remove(1);
+            let id = conflict[0].parse().unwrap_or(0);
+            let error = save_error(&*message).unwrap_or_else(|| OhmerError::RedisError(
+                    redis::RedisError::from((redis::ErrorKind::ResponseError, "Error reindexing object", message))));
+            conflicts.push((id, error));
+        }
+    }
+    Ok(conflicts)
+}
+
+/// Connections accepted in place of a client: a reference to a client, to
+/// a connection, to a `Traced` connection, or to a reference to them.
+pub trait Connection<'a> {
+    /// Gets the connection to send the requests to.
+    fn connection(self) -> &'a redis::ConnectionLike;
+}
+
+impl<'a> Connection<'a> for &'a redis::Client {
+    fn connection(self) -> &'a redis::ConnectionLike {
+        self
+    }
+}
+
+impl<'a> Connection<'a> for &'a redis::Connection {
+    fn connection(self) -> &'a redis::ConnectionLike {
+        self
+    }
+}
+
+impl<'a, 'b> Connection<'a> for &'a trace::Traced<'b> {
+    fn connection(self) -> &'a redis::ConnectionLike {
+        self
+    }
+}
+
+impl<'a> Connection<'a> for &'a (redis::ConnectionLike + 'a) {
+    fn connection(self) -> &'a redis::ConnectionLike {
+        self
+    }
+}
+
+impl<'a, 'b, T: ?Sized> Connection<'a> for &'b &'a T where &'a T: Connection<'a> {
+    fn connection(self) -> &'a redis::ConnectionLike {
+        (*self).connection()
+    }
+}
+
 /// Structs that can be stored in and retrieved from Redis.
 /// You can use the `model!` macro as a helper.
 pub trait Ohmer : rustc_serialize::Encodable + rustc_serialize::Decodable + Default + Sized {
@@ -703,6 +1487,53 @@ pub trait Ohmer : rustc_serialize::Encodable + rustc_serialize::Decodable + Defa
     /// Fields with an index.
     fn index_fields<'a>(&self) -> HashSet<&'a str> { HashSet::new() }
 
+    /// Groups of fields whose combined values are unique. Each group is
+    /// stored as a unique index named after its fields joined by `+`.
+    fn composite_unique_fields<'a>(&self) -> Vec<Vec<&'a str>> { vec![] }
+
+    /// Groups of fields indexed together. Each group is stored as an index
+    /// named after its fields joined by `+`, with their values joined by `:`.
+    fn composite_index_fields<'a>(&self) -> Vec<Vec<&'a str>> { vec![] }
+
+    /// Fields indexed by each of their words, for full-text search.
+    fn text_fields<'a>(&self) -> HashSet<&'a str> { HashSet::new() }
+
+    /// Fields indexed in lexicographic order, to find them by prefix.
+    fn prefix_fields<'a>(&self) -> HashSet<&'a str> { HashSet::new() }
+
+    /// Geo indices, with the longitude and latitude fields locating the
+    /// object.
+    fn geo_fields<'a>(&self) -> Vec<(&'a str, &'a str, &'a str)> { vec![] }
+
+    /// Redis key of the geo set of an index.
+    fn key_for_geo(&self, name: &str) -> String {
+        format!("{}:geo:{}", self.get_class_name(), name)
+    }
+
+    /// Transformations applied to the value of `field` before it is used
+    /// in a unique or index key.
+    fn normalizers(&self, _field: &str) -> Vec<Normalizer> { vec![] }
+
+    /// Applies the normalizers of `field` to `value`.
+    fn normalize(&self, field: &str, value: &str) -> String {
+        self.normalizers(field).iter().fold(value.to_string(), |value, n| n.normalize(&*value))
+    }
+
+    /// Whether text fields are indexed by the stem of each word.
+    fn stemming(&self) -> bool { false }
+
+    /// Words that are not indexed in text fields.
+    fn stop_words(&self) -> &'static [&'static str] { &[] }
+
+    /// Splits the value of a text field in the terms to index and search.
+    fn terms(&self, _field: &str, value: &str) -> Vec<String> {
+        text::tokenize(value, self.stop_words(), self.stemming())
+    }
+
+    /// Whether `delete` keeps the object, its sets, lists and counters so it
+    /// can be restored.
+    fn soft_delete(&self) -> bool { false }
+
     /// Redis key to find an element with a unique index field value.
     fn key_for_unique(&self, field: &str, value: &str) -> String {
         format!("{}:uniques:{}:{}", self.get_class_name(), field, value)
@@ -713,6 +1544,18 @@ pub trait Ohmer : rustc_serialize::Encodable + rustc_serialize::Decodable + Defa
         format!("{}:indices:{}:{}", self.get_class_name(), field, value)
     }
 
+    /// Redis key of the sorted set with all elements scored by an indexed
+    /// timestamp field.
+    fn key_for_sorted(&self, field: &str) -> String {
+        format!("{}:sorted:{}", self.get_class_name(), field)
+    }
+
+    /// Redis key of the lexicographic sorted set with the values of a
+    /// prefix field, as `value:id` members.
+    fn key_for_prefix(&self, field: &str) -> String {
+        format!("{}:prefix:{}", self.get_class_name(), field)
+    }
+
     /// Name of all the fields that are counters. Counters are stored
     /// independently to keep atomicity in its operations.
     fn counters(&self) -> HashSet<String> {
@@ -729,12 +1572,10 @@ pub trait Ohmer : rustc_serialize::Encodable + rustc_serialize::Decodable + Defa
     }
 
     /// Loads an object by id.
-    fn load(&mut self, id: usize, r: &redis::Client) -> Result<(), DecoderError> {
-        let mut properties:HashMap<String, String> = try!(try!(r.get_connection()).hgetall(format!("{}:{}", self.get_class_name(), id)));
-        properties.insert("id".to_string(), format!("{}", id));
-
-        let mut decoder = Decoder::new(properties);
-        *self = try!(rustc_serialize::Decodable::decode(&mut decoder));
+    fn load<'a>(&mut self, id: usize, r: impl Connection<'a>) -> Result<(), DecoderError> {
+        let r = r.connection();
+        let properties:HashMap<String, Vec<u8>> = try!(traced(r).hgetall(format!("{}:{}", self.get_class_name(), id)));
+        *self = try!(decode_hash(id, properties));
         Ok(())
     }
 
@@ -751,58 +1592,140 @@ pub trait Ohmer : rustc_serialize::Encodable + rustc_serialize::Decodable + Defa
             ) -> Result<(HashMap<String, String>, HashMap<String, Vec<String>>), OhmerError> {
         let mut unique_fields = self.unique_fields();
         let mut index_fields = self.index_fields();
+        let text_fields = self.text_fields();
+        let composite_uniques = self.composite_unique_fields();
+        let composite_indices = self.composite_index_fields();
+        let composite_fields = composite_uniques.iter().chain(composite_indices.iter())
+            .flat_map(|fields| fields.iter().cloned()).collect::<HashSet<_>>();
         let mut uniques = HashMap::new();
         let mut indices = HashMap::new();
+        let mut values = HashMap::new();
 
         for i in 0..(encoder.attributes.len() / 2) {
             let pos = i * 2;
-            let key = &encoder.attributes[pos];
-            if unique_fields.remove(&**key) {
-                uniques.insert(key.clone(), encoder.attributes[pos + 1].clone());
+            let key = try!(String::from_utf8(encoder.attributes[pos].clone()));
+            if is_timestamp(encoder, &*key) {
+                // timestamps are indexed in a sorted set by the server
+                index_fields.remove(&*key);
+                continue;
+            }
+            if !unique_fields.contains(&*key) && !text_fields.contains(&*key) &&
+                    !index_fields.contains(&*key) && !composite_fields.contains(&*key) &&
+                    !(key.len() > 3 && &key[key.len() - 3..] == "_id") {
+                // not indexed, the value may not be valid UTF-8
+                continue;
+            }
+            let value = self.normalize(&*key, &*try!(String::from_utf8(encoder.attributes[pos + 1].clone())));
+            if key.len() > 3 && &key[key.len() - 3..] == "_id" {
+                // references may be part of a composite by their field name
+                values.insert(key[..key.len() - 3].to_string(), value.clone());
+            }
+            values.insert(key.clone(), value.clone());
+            if unique_fields.remove(&*key) {
+                uniques.insert(key.clone(), value.clone());
             }
-            if index_fields.remove(&**key) {
-                indices.insert(key.clone(), vec![encoder.attributes[pos + 1].clone()]);
+            if text_fields.contains(&*key) {
+                indices.insert(key.clone(), self.terms(&*key, &*value));
+                continue;
+            }
+            if index_fields.remove(&*key) {
+                indices.insert(key.clone(), vec![value]);
             } else if key.len() > 3 && &key[key.len() - 3..] == "_id" &&
                 index_fields.remove(&key[..key.len() - 3]) {
-                indices.insert(key.clone(), vec![encoder.attributes[pos + 1].clone()]);
+                indices.insert(key.clone(), vec![value]);
             }
         }
         if unique_fields.len() > 0 {
             return Err(OhmerError::UnknownIndex(unique_fields.iter().next().unwrap().to_string()));
         }
+        for fields in composite_uniques.iter() {
+            // a group with a missing value is not constrained
+            if let Some(value) = composite_value(fields, &values) {
+                uniques.insert(fields.join("+"), value);
+            }
+        }
+        for fields in composite_indices.iter() {
+            if let Some(value) = composite_value(fields, &values) {
+                indices.insert(fields.join("+"), vec![value]);
+            }
+        }
         Ok((uniques, indices))
 
     }
 
     /// Saves the object in the database, and sets the instance `id` if it was
-    /// not set.
-    fn save(&mut self, r: &redis::Client) -> Result<(), OhmerError> {
+    /// not set. `CreatedAt` and `UpdatedAt` fields are set using the server
+    /// time.
+    fn save<'a>(&mut self, r: impl Connection<'a>) -> Result<(), OhmerError> {
+        let r = r.connection();
+        save_object(self, None, r)
+    }
+
+    /// Saves the object in the database and sets it to expire in `ttl`
+    /// seconds, in a single request.
+    fn save_with_ttl<'a>(&mut self, ttl: usize, r: impl Connection<'a>) -> Result<(), OhmerError> {
+        let r = r.connection();
+        save_object(self, Some(ttl), r)
+    }
+
+    /// Sets the object and its sets, lists and counters to expire in `ttl`
+    /// seconds. Saving the object again keeps the expiration.
+    /// Indices and uniques are removed by `purge_expired` once it expired.
+    fn expire<'a>(&self, ttl: usize, r: impl Connection<'a>) -> Result<(), OhmerError> {
+        let r = r.connection();
+        let id = self.id();
+        if id == 0 {
+            return Err(OhmerError::NotSaved);
+        }
         let encoder = try!(self.encoder());
-        let (uniques, indices) = try!(self.uniques_indices(&encoder));
-        let script = redis::Script::new(SAVE);
-        let result = script
-                .arg(try!(msgpack_encode(&encoder.features)))
-                .arg(try!(msgpack_encode(&encoder.attributes.iter().map(|x| &*x).collect::<Vec<_>>())))
-                .arg(try!(msgpack_encode(&indices)))
-                .arg(try!(msgpack_encode(&uniques)))
-                .invoke(&try!(r.get_connection()));
-        let id = match result {
-            Ok(id) => id,
-            Err(e) => {
-                let re = Regex::new(r"UniqueIndexViolation: (\w+)").unwrap();
-                let s = format!("{}", e);
-                match re.find(&*s) {
-                    Some((start, stop)) => return Err(OhmerError::UniqueIndexViolation(s[start + 22..stop].to_string())),
-                    None => return Err(OhmerError::RedisError(e)),
-                }
-            },
-        };
-        self.set_id(id);
+        let tracked = tracked_keys(self, &encoder, &*format!("{}", id));
+
+        let mut model = HashMap::new();
+        let name = self.get_class_name();
+        model.insert("key", format!("{}:{}", name, id));
+        model.insert("id", format!("{}", id));
+        model.insert("name", name);
+
+        let script = lua_script(EXPIRE);
+        let _:() = try!(script
+                .arg(try!(msgpack_encode(&model)))
+                .arg(ttl)
+                .arg(try!(msgpack_encode(&tracked)))
+                .invoke(&traced(r)));
         Ok(())
     }
 
-    /// Deletes the object from the database.
-    fn delete(self, r: &redis::Client) -> Result<(), OhmerError> {
+    /// Deletes the object from the database. If `soft_delete` is enabled,
+    /// the object is only removed from `all`, the indices and the uniques,
+    /// and can be restored using `restore`.
+    fn delete<'a>(self, r: impl Connection<'a>) -> Result<(), OhmerError> {
+        let r = r.connection();
+        if !self.soft_delete() {
+            return self.hard_delete(r);
+        }
+
+        let id = self.id();
+        if id == 0 {
+            return Err(OhmerError::NotSaved);
+        }
+
+        let mut model = HashMap::new();
+        let name = self.get_class_name();
+        model.insert("key", format!("{}:{}", name, id));
+        model.insert("id", format!("{}", id));
+        model.insert("name", name);
+
+        let script = lua_script(SOFT_DELETE);
+        let _:() = try!(script
+                .arg(try!(msgpack_encode(&model)))
+                .invoke(&traced(r)));
+        Ok(())
+    }
+
+    /// Deletes the object from the database, including its sets, lists and
+    /// counters, even if `soft_delete` is enabled.
+    fn hard_delete<'a>(self, r: impl Connection<'a>) -> Result<(), OhmerError> {
+        let r = r.connection();
         let encoder = try!(self.encoder());
         let (uniques, _) = try!(self.uniques_indices(&encoder));
 
@@ -817,14 +1740,153 @@ pub trait Ohmer : rustc_serialize::Encodable + rustc_serialize::Decodable + Defa
         model.insert("id", format!("{}", id));
         model.insert("name", name);
 
-        let script = redis::Script::new(DELETE);
-        let _:() = try!(script
-                .arg(try!(msgpack_encode(&model)))
-                .arg(try!(msgpack_encode(&uniques)))
-                .arg(try!(msgpack_encode(&tracked)))
-                .invoke(&try!(r.get_connection())));
-        Ok(())
+        let script = lua_script(DELETE);
+        let _:() = try!(script
+                .arg(try!(msgpack_encode(&model)))
+                .arg(try!(msgpack_encode(&uniques)))
+                .arg(try!(msgpack_encode(&tracked)))
+                .invoke(&traced(r)));
+        Ok(())
+    }
+}
+
+/// Creates the script for `code`. The hash computed by redis-rs is wrong
+/// for lengths of 55 modulo 64, and invoking those scripts would load them
+/// again forever, so they get a trailing newline.
+fn lua_script(code: &str) -> redis::Script {
+    if code.len() % 64 == 55 {
+        redis::Script::new(&*format!("{}\n", code))
+    } else {
+        redis::Script::new(code)
+    }
+}
+
+/// Creates a script running `code` with the SAVE script available as a
+/// `save(ARGV)` function, to save several objects in a single call.
+fn save_script(code: &str) -> redis::Script {
+    lua_script(&*format!("local function save(ARGV)\n{}\nend\n{}", SAVE, code))
+}
+
+/// Saves `obj` with the SAVE script, setting it to expire in `ttl` seconds
+/// if given, and updates its id and timestamps.
+fn save_object<T: Ohmer>(obj: &mut T, ttl: Option<usize>, r: &redis::ConnectionLike) -> Result<(), OhmerError> {
+    let encoder = try!(obj.encoder());
+    let mut args = try!(save_args(obj, &encoder));
+    if let Some(ttl) = ttl {
+        // `*` stands for the id, not known yet for new objects
+        args.push(vec![]);
+        args.push(format!("{}", ttl).into_bytes());
+        args.push(try!(msgpack_encode(&tracked_keys(obj, &encoder, "*"))));
+    }
+    let script = lua_script(SAVE);
+    let result = script.arg(args).invoke(&traced(r));
+    let (id, time, created):(usize, u64, u64) = match result {
+        Ok(v) => v,
+        Err(e) => return Err(save_error(&*format!("{}", e)).unwrap_or(OhmerError::RedisError(e))),
+    };
+    if time > 0 {
+        try!(stamp(obj, &encoder, id, time, created));
+    }
+    obj.set_id(id);
+    Ok(())
+}
+
+/// Arguments of the SAVE script for `obj`.
+fn save_args<T: Ohmer>(obj: &T, encoder: &Encoder) -> Result<Vec<Vec<u8>>, OhmerError> {
+    let (uniques, indices) = try!(obj.uniques_indices(encoder));
+    Ok(vec![
+        try!(msgpack_encode(&encoder.features)),
+        try!(msgpack_encode_bytes(&encoder.attributes)),
+        try!(msgpack_encode(&indices)),
+        try!(msgpack_encode(&uniques)),
+        try!(msgpack_encode(&sorted_fields(obj, encoder))),
+        try!(msgpack_encode(&obj.prefix_fields())),
+        try!(msgpack_encode(&geo_fields(obj))),
+    ])
+}
+
+/// Error raised by the SAVE script validations, if `message` has one.
+fn save_error(message: &str) -> Option<OhmerError> {
+    let re = Regex::new(r"UniqueIndexViolation: ([\w+]+)").unwrap();
+    if let Some((start, stop)) = re.find(message) {
+        return Some(OhmerError::UniqueIndexViolation(message[start + 22..stop].to_string()));
+    }
+    let re = Regex::new(r"InvalidCoordinates: (\w+)").unwrap();
+    re.find(message).map(|(start, stop)| OhmerError::InvalidCoordinates(message[start + 20..stop].to_string()))
+}
+
+/// Error for a script reply without the expected values.
+fn reply_error(message: &'static str) -> OhmerError {
+    OhmerError::RedisError(redis::RedisError::from((redis::ErrorKind::ResponseError, message)))
+}
+
+/// Longitude and latitude fields of each geo index.
+fn geo_fields<T: Ohmer>(obj: &T) -> HashMap<String, Vec<String>> {
+    obj.geo_fields().iter().map(|&(name, lon, lat)| (name.to_string(), vec![lon.to_string(), lat.to_string()])).collect()
+}
+
+/// Joins the values of a group of fields by `:`, if all of them are present.
+/// Backslashes and colons in a value are escaped with a backslash, so
+/// `("a:b", "c")` and `("a", "b:c")` do not share a key.
+fn composite_value(fields: &[&str], values: &HashMap<String, String>) -> Option<String> {
+    let values = fields.iter().map(|field| values.get(*field).map(|value| value.replace("\\", "\\\\").replace(":", "\\:")))
+        .collect::<Option<Vec<_>>>();
+    values.map(|values| values.join(":"))
+}
+
+/// Checks if `field` is a `CreatedAt` or `UpdatedAt` field.
+fn is_timestamp(encoder: &Encoder, field: &str) -> bool {
+    ["created_at", "updated_at"].iter().any(|feature| encoder.features.get(*feature).map(|f| &**f == field).unwrap_or(false))
+}
+
+/// Keys storing the sets, lists and counters of the object with `id`.
+fn tracked_keys<T: Ohmer>(obj: &T, encoder: &Encoder, id: &str) -> Vec<String> {
+    let class_name = obj.get_class_name();
+    let mut keys = vec![];
+    for counter in encoder.counters.iter() {
+        keys.push(format!("{}:{}:{}", class_name, id, counter));
+    }
+    for property in encoder.sets.iter().chain(encoder.lists.iter()) {
+        keys.push(format!("{}:{}:{}", class_name, property, id));
+    }
+    keys
+}
+
+/// Timestamp fields that are also indices, kept in sorted sets.
+fn sorted_fields<T: Ohmer>(obj: &T, encoder: &Encoder) -> Vec<String> {
+    let index_fields = obj.index_fields();
+    ["created_at", "updated_at"].iter()
+        .filter_map(|feature| encoder.features.get(*feature))
+        .filter(|field| index_fields.contains(&***field))
+        .cloned()
+        .collect()
+}
+
+/// Decodes an object from the fields of its hash, as read by HGETALL.
+fn decode_hash<T: Ohmer>(id: usize, mut properties: HashMap<String, Vec<u8>>) -> Result<T, DecoderError> {
+    properties.insert("id".to_string(), format!("{}", id).into_bytes());
+    let mut decoder = Decoder::new(properties);
+    rustc_serialize::Decodable::decode(&mut decoder)
+}
+
+/// Updates the timestamp fields after the object was saved, decoding it
+/// from the saved attributes.
+fn stamp<T: Ohmer>(obj: &mut T, encoder: &Encoder, id: usize, time: u64, created: u64) -> Result<(), OhmerError> {
+    let mut properties = HashMap::new();
+    for pair in encoder.attributes.chunks(2) {
+        properties.insert(try!(String::from_utf8(pair[0].clone())), pair[1].clone());
+    }
+    if let Some(field) = encoder.features.get("updated_at") {
+        properties.insert(field.clone(), format!("{}", time).into_bytes());
+    }
+    if let Some(field) = encoder.features.get("created_at") {
+        properties.insert(field.clone(), format!("{}", created).into_bytes());
     }
+    properties.insert("id".to_string(), format!("{}", id).into_bytes());
+
+    let mut decoder = Decoder::new(properties);
+    *obj = try!(rustc_serialize::Decodable::decode(&mut decoder));
+    Ok(())
 }
 
 /// A Reference to another Ohmer object.
@@ -875,7 +1937,8 @@ impl<T: Ohmer> Reference<T> {
     }
 
     /// Returns a new instance of the referenced object.
-    pub fn get(&self, r: &redis::Client) -> Result<T, DecoderError> {
+    pub fn get<'a>(&self, r: impl Connection<'a>) -> Result<T, DecoderError> {
+        let r = r.connection();
         get(self.id, r)
     }
 
@@ -930,7 +1993,8 @@ impl<T: Ohmer> Collection<T> {
     }
 
     /// Returns a query for all T elements referencing this object.
-    pub fn all<'a, P: Ohmer>(&'a self, property: &str, parent: &P, r: &'a redis::Client) -> Query<T> {
+    pub fn all<'a, P: Ohmer>(&'a self, property: &str, parent: &P, r: impl Connection<'a>) -> Query<T> {
+        let r = r.connection();
         Query::<T>::find(&*format!("{}_id", property.to_ascii_lowercase()), &*format!("{}", parent.id()), r)
     }
 }
@@ -988,47 +2052,54 @@ impl<T: Ohmer> List<T> {
     }
 
     /// Number of items in the list.
-    pub fn len<P: Ohmer>(&self, property: &str, parent: &P, r: &redis::Client) -> Result<usize, OhmerError> {
-        Ok(try!(r.llen(try!(self.key_name(property, parent)))))
+    pub fn len<'a, P: Ohmer>(&self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<usize, OhmerError> {
+        let r = r.connection();
+        Ok(try!(traced(r).llen(try!(self.key_name(property, parent)))))
     }
 
     /// Adds an element at the end of the list.
-    pub fn push_back<P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: &redis::Client) -> Result<(), OhmerError> {
-        Ok(try!(r.rpush(try!(self.key_name(property, parent)), obj.id())))
+    pub fn push_back<'a, P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: impl Connection<'a>) -> Result<(), OhmerError> {
+        let r = r.connection();
+        Ok(try!(traced(r).rpush(try!(self.key_name(property, parent)), obj.id())))
     }
 
     /// Takes an element from the end of the list.
-    pub fn pop_back<P: Ohmer>(&self, property: &str, parent: &P, r: &redis::Client) -> Result<Option<T>, OhmerError> {
-        Ok(match try!(r.rpop(try!(self.key_name(property, parent)))) {
+    pub fn pop_back<'a, P: Ohmer>(&self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<Option<T>, OhmerError> {
+        let r = r.connection();
+        Ok(match try!(traced(r).rpop(try!(self.key_name(property, parent)))) {
             Some(id) => Some(try!(get(id, r))),
             None => None,
         })
     }
 
     /// Adds an element at the beginning of the list.
-    pub fn push_front<P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: &redis::Client) -> Result<(), OhmerError> {
-        Ok(try!(r.lpush(try!(self.key_name(property, parent)), obj.id())))
+    pub fn push_front<'a, P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: impl Connection<'a>) -> Result<(), OhmerError> {
+        let r = r.connection();
+        Ok(try!(traced(r).lpush(try!(self.key_name(property, parent)), obj.id())))
     }
 
     /// Takes an element from the beginning of the list.
-    pub fn pop_front<P: Ohmer>(&self, property: &str, parent: &P, r: &redis::Client) -> Result<Option<T>, OhmerError> {
-        Ok(match try!(r.lpop(try!(self.key_name(property, parent)))) {
+    pub fn pop_front<'a, P: Ohmer>(&self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<Option<T>, OhmerError> {
+        let r = r.connection();
+        Ok(match try!(traced(r).lpop(try!(self.key_name(property, parent)))) {
             Some(id) => Some(try!(get(id, r))),
             None => None,
         })
     }
 
     /// Retrieves an element from the beginning of the list.
-    pub fn first<P: Ohmer>(&self, property: &str, parent: &P, r: &redis::Client) -> Result<Option<T>, OhmerError> {
-        Ok(match try!(r.lindex(try!(self.key_name(property, parent)), 0)) {
+    pub fn first<'a, P: Ohmer>(&self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<Option<T>, OhmerError> {
+        let r = r.connection();
+        Ok(match try!(traced(r).lindex(try!(self.key_name(property, parent)), 0)) {
             Some(id) => Some(try!(get(id, r))),
             None => None,
         })
     }
 
     /// Retrieves an element from the end of the list.
-    pub fn last<P: Ohmer>(&self, property: &str, parent: &P, r: &redis::Client) -> Result<Option<T>, OhmerError> {
-        Ok(match try!(r.lindex(try!(self.key_name(property, parent)), -1)) {
+    pub fn last<'a, P: Ohmer>(&self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<Option<T>, OhmerError> {
+        let r = r.connection();
+        Ok(match try!(traced(r).lindex(try!(self.key_name(property, parent)), -1)) {
             Some(id) => Some(try!(get(id, r))),
             None => None,
         })
@@ -1036,25 +2107,29 @@ impl<T: Ohmer> List<T> {
 
     /// Creates an iterator for the list between `start` and `end`.
     /// Negative indices start from the end.
-    pub fn try_range<'a, P: Ohmer>(&'a self, property: &str, parent: &P, start: isize, end: isize, r: &'a redis::Client) -> Result<Iter<T>, OhmerError> {
-        let ids:Vec<usize> = try!(r.lrange(try!(self.key_name(property, parent)), start, end));
+    pub fn try_range<'a, P: Ohmer>(&'a self, property: &str, parent: &P, start: isize, end: isize, r: impl Connection<'a>) -> Result<Iter<T>, OhmerError> {
+        let r = r.connection();
+        let ids:Vec<usize> = try!(traced(r).lrange(try!(self.key_name(property, parent)), start, end));
         Ok(Iter::new(ids.into_iter(), r))
     }
 
     /// Creates an iterator for all the elements in the list.
-    pub fn try_iter<'a, P: Ohmer>(&'a self, property: &str, parent: &P, r: &'a redis::Client) -> Result<Iter<T>, OhmerError> {
+    pub fn try_iter<'a, P: Ohmer>(&'a self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<Iter<T>, OhmerError> {
+        let r = r.connection();
         self.try_range(property, parent, 0, -1, r)
     }
 
     /// Checks if an element is in the list.
-    pub fn contains<P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: &redis::Client) -> Result<bool, OhmerError> {
-        let ids:Vec<usize> = try!(r.lrange(try!(self.key_name(property, parent)), 0, -1));
+    pub fn contains<'a, P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: impl Connection<'a>) -> Result<bool, OhmerError> {
+        let r = r.connection();
+        let ids:Vec<usize> = try!(traced(r).lrange(try!(self.key_name(property, parent)), 0, -1));
         Ok(ids.contains(&obj.id()))
     }
 
     /// Remove all occurrences of an element in the list.
-    pub fn remove<P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: &redis::Client) -> Result<usize, OhmerError> {
-        Ok(try!(r.lrem(try!(self.key_name(property, parent)), 0, obj.id())))
+    pub fn remove<'a, P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: impl Connection<'a>) -> Result<usize, OhmerError> {
+        let r = r.connection();
+        Ok(try!(traced(r).lrem(try!(self.key_name(property, parent)), 0, obj.id())))
     }
 }
 
@@ -1116,31 +2191,54 @@ impl<T: Ohmer> Set<T> {
     }
 
     /// Gets a `Query` object for all the elements in the set.
-    pub fn query<'a, P: Ohmer>(&'a self, property: &str, parent: &P, r: &'a redis::Client) -> Result<Query<T>, OhmerError> {
+    pub fn query<'a, P: Ohmer>(&'a self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<Query<T>, OhmerError> {
+        let r = r.connection();
         let key = try!(self.key(property, parent));
         Ok(Query::new(key, r))
     }
 
     /// Adds an element to the set. Returns true when the element was added,
     /// false if it was already present.
-    pub fn insert<P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: &redis::Client) -> Result<bool, OhmerError> {
-        Ok(try!(r.sadd(try!(self.key_name(property, parent)), obj.id())))
+    pub fn insert<'a, P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: impl Connection<'a>) -> Result<bool, OhmerError> {
+        let r = r.connection();
+        Ok(try!(traced(r).sadd(try!(self.key_name(property, parent)), obj.id())))
     }
 
     /// Removes an element to the set. Returns true when the element was removed,
     /// false if it was already absent.
-    pub fn remove<P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: &redis::Client) -> Result<bool, OhmerError> {
-        Ok(try!(r.srem(try!(self.key_name(property, parent)), obj.id())))
+    pub fn remove<'a, P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: impl Connection<'a>) -> Result<bool, OhmerError> {
+        let r = r.connection();
+        Ok(try!(traced(r).srem(try!(self.key_name(property, parent)), obj.id())))
     }
 
     /// Returns true if the element is in the set.
-    pub fn contains<P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: &redis::Client) -> Result<bool, OhmerError> {
-        Ok(try!(r.sismember(try!(self.key_name(property, parent)), obj.id())))
+    pub fn contains<'a, P: Ohmer>(&self, property: &str, parent: &P, obj: &T, r: impl Connection<'a>) -> Result<bool, OhmerError> {
+        let r = r.connection();
+        Ok(try!(traced(r).sismember(try!(self.key_name(property, parent)), obj.id())))
     }
 
     /// Counts the number of elements in the set.
-    pub fn len<P: Ohmer>(&self, property: &str, parent: &P, r: &redis::Client) -> Result<usize, OhmerError> {
-        Ok(try!(r.scard(try!(self.key_name(property, parent)))))
+    pub fn len<'a, P: Ohmer>(&self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<usize, OhmerError> {
+        let r = r.connection();
+        Ok(try!(traced(r).scard(try!(self.key_name(property, parent)))))
+    }
+
+    /// Creates an iterator of `n` random elements of the set. If `replace`
+    /// is true, the same element may be returned more than once, and `n`
+    /// elements are returned even if the set is smaller.
+    pub fn random<'a, P: Ohmer>(&'a self, property: &str, parent: &P, n: usize, replace: bool, r: impl Connection<'a>) -> Result<Iter<T>, OhmerError> {
+        let r = r.connection();
+        try!(self.query(property, parent, r)).sample(n, replace)
+    }
+
+    /// Removes a random element from the set and returns it, or `None` if
+    /// the set is empty.
+    pub fn pop_random<'a, P: Ohmer>(&self, property: &str, parent: &P, r: impl Connection<'a>) -> Result<Option<T>, OhmerError> {
+        let r = r.connection();
+        Ok(match try!(traced(r).spop(try!(self.key_name(property, parent)))) {
+            Some(id) => Some(try!(get(id, r))),
+            None => None,
+        })
     }
 }
 
@@ -1158,6 +2256,22 @@ pub enum OhmerError {
     UnknownIndex(String),
     /// A unique field value is already in use. The field name is returned.
     UniqueIndexViolation(String),
+    /// A longitude or latitude is out of range. The geo index name is
+    /// returned.
+    InvalidCoordinates(String),
+    /// The operation requires a query over a single stored set, like
+    /// `Class:all` or the result of `Query::store`.
+    NotStored,
+    /// The field cannot be updated in bulk, because it is not an attribute,
+    /// or is part of a composite or geo index, or a timestamp. The field
+    /// name is returned.
+    NotUpdatable(String),
+    /// A page cursor is not one returned with a previous page. The cursor
+    /// is returned.
+    InvalidCursor(String),
+    /// A key with the new name of a renamed model is already in use. The
+    /// key is returned.
+    KeyExists(String),
     /// There was an error translating a field to a string using utf8.
     CommandError(Vec<u8>),
 }
@@ -1224,15 +2338,17 @@ impl Counter {
     }
 
     /// Increments the counter by `incr` and returns the new value.
-    pub fn incr<T: Ohmer>(&self, obj: &T, prop: &str, incr: i64, r: &redis::Client) -> Result<i64, OhmerError> {
+    pub fn incr<'a, T: Ohmer>(&self, obj: &T, prop: &str, incr: i64, r: impl Connection<'a>) -> Result<i64, OhmerError> {
+        let r = r.connection();
         let key = try!(self.get_key(obj, prop));
-        Ok(try!(r.incr(key, incr)))
+        Ok(try!(traced(r).incr(key, incr)))
     }
 
     /// Gets the current counter value.
-    pub fn get<T: Ohmer>(&self, obj: &T, prop: &str, r: &redis::Client) -> Result<i64, OhmerError> {
+    pub fn get<'a, T: Ohmer>(&self, obj: &T, prop: &str, r: impl Connection<'a>) -> Result<i64, OhmerError> {
+        let r = r.connection();
         let key = try!(self.get_key(obj, prop));
-        let r:Option<i64> = try!(r.get(key));
+        let r:Option<i64> = try!(traced(r).get(key));
         Ok(r.unwrap_or(0))
     }
 }
@@ -1261,6 +2377,82 @@ macro_rules! decr {
     }}
 }
 
+/// Time when an object was first saved, in seconds since the Unix epoch.
+/// It is set using the server time, so all clients agree on it.
+///
+/// When declared as part of the `indices`, objects are also kept in a sorted
+/// set and can be queried by time using `Query::range`.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::{Ohmer, CreatedAt, UpdatedAt};
+/// model!(
+///     Post {
+///         indices { created_at:CreatedAt = CreatedAt::new(); };
+///         updated_at:UpdatedAt = UpdatedAt::new();
+///         title:String = "".to_string();
+///     });
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let mut post = create!(Post { title: "Hello".to_string(), }, &client).unwrap();
+/// assert!(post.created_at.time() > 0);
+/// assert_eq!(post.created_at.time(), post.updated_at.time());
+/// post.title = "Hello World".to_string();
+/// post.save(&client).unwrap();
+/// assert!(post.created_at.time() <= post.updated_at.time());
+/// # }
+/// ```
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone)]
+pub struct CreatedAt {
+    time: u64,
+}
+
+impl CreatedAt {
+    /// Creates a timestamp for an object that was not saved yet.
+    pub fn new() -> Self {
+        CreatedAt { time: 0 }
+    }
+
+    /// Seconds since the Unix epoch. It is 0 if the object was not saved.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+}
+
+/// Time when an object was last saved, in seconds since the Unix epoch.
+/// It is set using the server time, so all clients agree on it.
+///
+/// When declared as part of the `indices`, objects are also kept in a sorted
+/// set and can be queried by time using `Query::range`.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug, Clone)]
+pub struct UpdatedAt {
+    time: u64,
+}
+
+impl UpdatedAt {
+    /// Creates a timestamp for an object that was not saved yet.
+    pub fn new() -> Self {
+        UpdatedAt { time: 0 }
+    }
+
+    /// Seconds since the Unix epoch. It is 0 if the object was not saved.
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+}
+
+/// Counter for temporary keys used by queries and migrations.
+static TEMPORARY_KEYS: AtomicUsize = AtomicUsize::new(0);
+
+/// Name for a new temporary key.
+fn temporary_key() -> String {
+    format!("ohmers:tmp:{}", TEMPORARY_KEYS.fetch_add(1, Ordering::SeqCst))
+}
+
 /// A query of a set, or a result of set operations.
 ///
 /// # Examples
@@ -1297,30 +2489,368 @@ macro_rules! decr {
 /// ```
 pub struct Query<'a, T: 'a + Ohmer> {
     set: stal::Set,
-    r: &'a redis::Client,
+    r: &'a redis::ConnectionLike,
     phantom: PhantomData<T>,
+    /// Operations creating temporary sets used by `set`.
+    preps: Vec<Vec<Vec<u8>>>,
+    /// Temporary keys to delete after running the query.
+    temps: Vec<Vec<u8>>,
 }
 
 impl<'a, T: Ohmer> Query<'a, T> {
     /// Create a new Query for a Set
-    pub fn new(set: stal::Set, r: &'a redis::Client) -> Self {
-        Query { set: set, phantom: PhantomData, r: r }
+    pub fn new(set: stal::Set, r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        Query { set: set, phantom: PhantomData, r: r, preps: vec![], temps: vec![] }
     }
 
     /// Creates a new query with the intersection of all key/value
-    pub fn from_keys(kv: &[(&str, &str)], r: &'a redis::Client) -> Self {
-        let set = stal::Set::Inter(kv.iter().map(|kv| Query::<T>::key(kv.0, kv.1)).collect());
-        Query::new(set, r)
+    pub fn from_keys(kv: &[(&str, &str)], r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        Query::new(Query::<T>::keys(kv), r)
+    }
+
+    /// Creates the stal set with the intersection of all key/value
+    /// combinations, using composite indices when all their fields are
+    /// present.
+    pub fn keys(kv: &[(&str, &str)]) -> stal::Set {
+        let obj = T::default();
+        let mut groups = obj.composite_index_fields();
+        // prefer the groups covering more fields
+        groups.sort_by(|a, b| b.len().cmp(&a.len()));
+
+        let mut remaining = kv.iter().cloned().collect::<HashMap<_, _>>();
+        let mut sets = vec![];
+        for fields in groups {
+            if !fields.iter().all(|field| remaining.contains_key(field)) {
+                continue;
+            }
+            let values = fields.iter()
+                .map(|field| (field.to_string(), obj.normalize(field, remaining.remove(field).unwrap())))
+                .collect();
+            let value = composite_value(&*fields, &values).unwrap();
+            sets.push(stal::Set::Key(obj.key_for_index(&*fields.join("+"), &*value).as_bytes().to_vec()));
+        }
+        // keep the order of the remaining fields
+        for &(field, value) in kv.iter() {
+            if remaining.contains_key(field) {
+                sets.push(Query::<T>::key(field, value));
+            }
+        }
+        stal::Set::Inter(sets)
+    }
+
+    /// Creates the stal set with all the elements that are not in `set`.
+    pub fn not(set: stal::Set) -> stal::Set {
+        let all = format!("{}:all", T::default().get_class_name()).as_bytes().to_vec();
+        stal::Set::Diff(vec![stal::Set::Key(all), set])
     }
 
     /// Creates the stal set for a key/value combination
     pub fn key(field: &str, value: &str) -> stal::Set {
-        stal::Set::Key(T::default().key_for_index(field, value).as_bytes().to_vec())
+        let obj = T::default();
+        stal::Set::Key(obj.key_for_index(field, &*obj.normalize(field, value)).as_bytes().to_vec())
     }
 
     /// Creates a query for a key/value combination
-    pub fn find(field: &str, value: &str, r: &'a redis::Client) -> Self {
-        Query { set: Query::<T>::key(field, value), phantom: PhantomData, r: r }
+    pub fn find(field: &str, value: &str, r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        Query::new(Query::<T>::key(field, value), r)
+    }
+
+    /// Creates a query for all elements whose text `field` contains all
+    /// the words in `text`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query};
+    /// model!(
+    ///     Invitation {
+    ///         stemming;
+    ///         text { message:String = "".to_string(); };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// let invitation = create!(Invitation { message: "Come to my Birthday Party!".to_string(), }, &client).unwrap();
+    /// assert!(Query::<Invitation>::search("message", "birthday parties", &client)
+    ///     .try_into_iter().unwrap().any(|i| i == invitation));
+    /// # }
+    /// ```
+    pub fn search(field: &str, text: &str, r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        Query::new(stal::Set::Inter(Query::<T>::term_keys(field, text)), r)
+    }
+
+    /// Creates a query for all elements whose text `field` contains any of
+    /// the words in `text`.
+    pub fn search_any(field: &str, text: &str, r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        Query::new(stal::Set::Union(Query::<T>::term_keys(field, text)), r)
+    }
+
+    /// Creates the stal sets for each of the terms in `text`.
+    fn term_keys(field: &str, text: &str) -> Vec<stal::Set> {
+        let mut terms = T::default().terms(field, text);
+        if terms.len() == 0 {
+            // no element is indexed without terms
+            terms.push("".to_string());
+        }
+        terms.iter().map(|term| Query::<T>::key(field, &*term)).collect()
+    }
+
+    /// Creates a query for all elements whose prefix `field` starts with
+    /// `prefix`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query};
+    /// model!(
+    ///     City {
+    ///         prefix { name:String = "".to_string(); };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// let city = create!(City { name: "Buenos Aires".to_string(), }, &client).unwrap();
+    /// assert!(Query::<City>::starts_with("name", "Buenos", &client)
+    ///     .try_into_iter().unwrap().any(|c| c == city));
+    /// # }
+    /// ```
+    pub fn starts_with(field: &str, prefix: &str, r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        let mut query = Query::new(stal::Set::Key(vec![]), r);
+        query.set = query.prefix_set(field, prefix);
+        query
+    }
+
+    /// Gets a temporary set with all elements whose prefix `field` starts
+    /// with `prefix`.
+    fn prefix_set(&mut self, field: &str, prefix: &str) -> stal::Set {
+        let key = T::default().key_for_prefix(field);
+        let mut max = format!("[{}", prefix).into_bytes();
+        max.push(0xff);
+        self.prep(vec![
+                b"ZRANGEBYLEX".to_vec(),
+                key.as_bytes().to_vec(),
+                format!("[{}", prefix).into_bytes(),
+                max,
+                ])
+    }
+
+    /// Creates a query for all elements located by the geo index `name`
+    /// within `km` kilometers of a point.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query};
+    /// model!(
+    ///     Stadium {
+    ///         geo { location: longitude, latitude; };
+    ///         longitude:f64 = 0.0;
+    ///         latitude:f64 = 0.0;
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// let stadium = create!(Stadium { longitude: -58.3646, latitude: -34.6356, }, &client).unwrap();
+    /// assert!(Query::<Stadium>::within_radius("location", -58.3816, -34.6037, 5.0, &client)
+    ///     .try_into_iter().unwrap().any(|s| s == stadium));
+    /// assert!(!Query::<Stadium>::within_radius("location", -58.3816, -34.6037, 1.0, &client)
+    ///     .try_into_iter().unwrap().any(|s| s == stadium));
+    /// # }
+    /// ```
+    pub fn within_radius(name: &str, lon: f64, lat: f64, km: f64, r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        let mut query = Query::new(stal::Set::Key(vec![]), r);
+        query.set = query.radius_set(name, lon, lat, km);
+        query
+    }
+
+    /// Creates a query for all elements located by the geo index `name`
+    /// within a box of `width` by `height` kilometers centered in a point.
+    /// Requires Redis 6.2 or newer.
+    pub fn within_box(name: &str, lon: f64, lat: f64, width: f64, height: f64, r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        let mut query = Query::new(stal::Set::Key(vec![]), r);
+        query.set = query.box_set(name, lon, lat, width, height);
+        query
+    }
+
+    /// Gets a temporary set with all elements within `km` kilometers of a
+    /// point.
+    fn radius_set(&mut self, name: &str, lon: f64, lat: f64, km: f64) -> stal::Set {
+        let key = T::default().key_for_geo(name);
+        self.prep(vec![
+                b"GEORADIUS".to_vec(),
+                key.as_bytes().to_vec(),
+                format!("{}", lon).as_bytes().to_vec(),
+                format!("{}", lat).as_bytes().to_vec(),
+                format!("{}", km).as_bytes().to_vec(),
+                b"km".to_vec(),
+                ])
+    }
+
+    /// Gets a temporary set with all elements within a box centered in a
+    /// point.
+    fn box_set(&mut self, name: &str, lon: f64, lat: f64, width: f64, height: f64) -> stal::Set {
+        let key = T::default().key_for_geo(name);
+        self.prep(vec![
+                b"GEOSEARCH".to_vec(),
+                key.as_bytes().to_vec(),
+                b"FROMLONLAT".to_vec(),
+                format!("{}", lon).as_bytes().to_vec(),
+                format!("{}", lat).as_bytes().to_vec(),
+                b"BYBOX".to_vec(),
+                format!("{}", width).as_bytes().to_vec(),
+                format!("{}", height).as_bytes().to_vec(),
+                b"km".to_vec(),
+                ])
+    }
+
+    /// Creates a query for all elements whose sorted index `field` is
+    /// between `min` and `max`, both inclusive. Use `f64::INFINITY` and
+    /// `f64::NEG_INFINITY` for open ranges.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query, CreatedAt};
+    /// # use std::f64;
+    /// model!(
+    ///     Comment {
+    ///         indices { created_at:CreatedAt = CreatedAt::new(); };
+    ///         body:String = "".to_string();
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// let comment = create!(Comment { body: "First!".to_string(), }, &client).unwrap();
+    /// let last_hour = (comment.created_at.time() - 3600) as f64;
+    /// assert!(Query::<Comment>::range("created_at", last_hour, f64::INFINITY, &client)
+    ///     .try_into_iter().unwrap().any(|c| c == comment));
+    /// # }
+    /// ```
+    pub fn range(field: &str, min: f64, max: f64, r: impl Connection<'a>) -> Self {
+        let r = r.connection();
+        let mut query = Query::new(stal::Set::Key(vec![]), r);
+        query.set = query.range_set(field, min, max);
+        query
+    }
+
+    /// Gets a temporary set with all elements whose sorted index `field`
+    /// is between `min` and `max`.
+    fn range_set(&mut self, field: &str, min: f64, max: f64) -> stal::Set {
+        let key = T::default().key_for_sorted(field);
+        self.prep(vec![
+                b"ZRANGEBYSCORE".to_vec(),
+                key.as_bytes().to_vec(),
+                format!("{}", min).as_bytes().to_vec(),
+                format!("{}", max).as_bytes().to_vec(),
+                ])
+    }
+
+    /// Creates a query for all elements whose reference `property` points
+    /// to any of the objects in `query`. The reference must be indexed.
+    /// The ids are resolved by the server, in the same transaction.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query, Reference};
+    /// # use redis::Commands;
+    /// model!(
+    ///     Stage {
+    ///         indices { city:String = "".to_string(); };
+    ///     });
+    ///
+    /// model!(
+    ///     Concert {
+    ///         indices {
+    ///             stage:Reference<Stage> = Reference::new();
+    ///             genre:String = "".to_string();
+    ///         };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// # let _:bool = client.del("Stage:indices:city:Lisbon").unwrap();
+    /// let stage = create!(Stage { city: "Lisbon".to_string(), }, &client).unwrap();
+    /// let other = create!(Stage { city: "Porto".to_string(), }, &client).unwrap();
+    /// let concert = create!(Concert { stage: Reference::with_value(&stage), genre: "fado".to_string(), }, &client).unwrap();
+    /// create!(Concert { stage: Reference::with_value(&other), genre: "fado".to_string(), }, &client).unwrap();
+    ///
+    /// let query = Query::<Concert>::via("stage", Query::<Stage>::find("city", "Lisbon", &client));
+    /// assert_eq!(query.ids().unwrap(), vec![concert.id]);
+    /// # }
+    /// ```
+    pub fn via<U: Ohmer>(property: &str, query: Query<'a, U>) -> Self {
+        let mut via = Query::new(stal::Set::Key(vec![]), query.r);
+        via.set = via.via_set(property, query);
+        via
+    }
+
+    /// Gets a temporary set with all elements whose reference `property`
+    /// points to any of the objects in `query`.
+    fn via_set<U: Ohmer>(&mut self, property: &str, query: Query<U>) -> stal::Set {
+        let key = temporary_key().into_bytes();
+        let prefix = T::default().key_for_index(&*format!("{}_id", property.to_ascii_lowercase()), "");
+        let template = vec![
+            b"EVAL".to_vec(),
+            VIA.as_bytes().to_vec(),
+            b"2".to_vec(),
+            key.clone(),
+            vec![],
+            prefix.into_bytes(),
+        ];
+        let (mut ops, _) = query.solve(stal::Stal::from_template(template, vec![(query.set.clone(), 4)]));
+        // the operations of `query` run inside this query transaction
+        ops.pop();
+        self.preps.extend(ops.into_iter().skip(1));
+        self.temps.push(key.clone());
+        stal::Set::Key(key)
+    }
+
+    /// Stores the result of `command` in a temporary set that is available
+    /// while the query runs.
+    fn prep(&mut self, command: Vec<Vec<u8>>) -> stal::Set {
+        let key = temporary_key().into_bytes();
+        let mut op = vec![b"EVAL".to_vec(), RANGE_STORE.as_bytes().to_vec(), b"1".to_vec(), key.clone()];
+        op.extend(command);
+        self.preps.push(op);
+        self.temps.push(key.clone());
+        stal::Set::Key(key)
+    }
+
+    /// Adds the preparation of temporary sets to the operations solved by
+    /// `stal`.
+    fn solve(&self, stal: stal::Stal) -> (Vec<Vec<Vec<u8>>>, usize) {
+        let (mut ops, pos) = stal.solve();
+        if self.preps.len() == 0 {
+            return (ops, pos);
+        }
+        let exec = ops.pop().unwrap();
+        let mut del = vec![b"DEL".to_vec()];
+        del.extend(self.temps.iter().cloned());
+        ops.push(del);
+        ops.push(exec);
+        for (i, prep) in self.preps.iter().enumerate() {
+            ops.insert(i + 1, prep.clone());
+        }
+        (ops, pos + self.preps.len())
     }
 
     /// Updates the set to be the intersection of the current one and
@@ -1366,90 +2896,742 @@ impl<'a, T: Ohmer> Query<'a, T> {
         self.set = stal::Set::Diff(sets);
     }
 
+    /// Updates the set to be the intersection of the current one and
+    /// the elements whose sorted index `field` is between `min` and `max`.
+    pub fn inter_range(&mut self, field: &str, min: f64, max: f64) -> &mut Self {
+        let set = self.range_set(field, min, max);
+        self.sinter(vec![set]);
+        self
+    }
+
+    /// Updates the set to be the intersection of the current set and the
+    /// elements whose reference `property` points to any of the objects in
+    /// `query`.
+    pub fn inter_via<U: Ohmer>(&mut self, property: &str, query: Query<U>) -> &mut Self {
+        let set = self.via_set(property, query);
+        self.sinter(vec![set]);
+        self
+    }
+
+    /// Updates the set to be the intersection of the current set and the
+    /// elements within `km` kilometers of a point.
+    pub fn inter_within_radius(&mut self, name: &str, lon: f64, lat: f64, km: f64) -> &mut Self {
+        let set = self.radius_set(name, lon, lat, km);
+        self.sinter(vec![set]);
+        self
+    }
+
+    /// Updates the set to be the intersection of the current set and the
+    /// elements within a box of `width` by `height` kilometers centered in
+    /// a point.
+    pub fn inter_within_box(&mut self, name: &str, lon: f64, lat: f64, width: f64, height: f64) -> &mut Self {
+        let set = self.box_set(name, lon, lat, width, height);
+        self.sinter(vec![set]);
+        self
+    }
+
+    /// Updates the set to be the intersection of the current set and the
+    /// elements whose prefix `field` starts with `prefix`.
+    pub fn inter_starts_with(&mut self, field: &str, prefix: &str) -> &mut Self {
+        let set = self.prefix_set(field, prefix);
+        self.sinter(vec![set]);
+        self
+    }
+
     /// Creates an iterator for all objects in the set.
     pub fn try_iter(&self) -> Result<Iter<'a, T>, OhmerError> {
-        Iter::from_ops(self.set.ids().solve(), self.r)
+        Iter::from_ops(self.solve(self.set.ids()), self.r)
+    }
+
+    /// Lists the commands `try_iter` sends to get the ids in the set,
+    /// including the preparation and removal of temporary keys.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::Query;
+    /// model!(
+    ///     Ticket {
+    ///         indices {
+    ///             queue:String = "".to_string();
+    ///             state:String = "".to_string();
+    ///         };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// let mut query = Query::<Ticket>::find("queue", "billing", &client);
+    /// query.diff("state", "closed");
+    /// assert_eq!(query.explain(), vec![
+    ///     "MULTI",
+    ///     "SDIFF Ticket:indices:queue:billing Ticket:indices:state:closed",
+    ///     "EXEC",
+    /// ]);
+    /// # }
+    /// ```
+    pub fn explain(&self) -> Vec<String> {
+        self.solve(self.set.ids()).0.iter().map(|op| trace::format_command(op)).collect()
+    }
+
+    /// Saves the ids in the set under the key `name`, expiring in `ttl`
+    /// seconds, or never if `ttl` is 0, and creates a query over it. The stored set can be used
+    /// later, or by other processes, with `Query::new(StalSet::Key(name))`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query, StalSet};
+    /// # use redis::Commands;
+    /// model!(
+    ///     Flight {
+    ///         indices {
+    ///             origin:String = "".to_string();
+    ///             destination:String = "".to_string();
+    ///         };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// # let _:bool = client.del("Flight:indices:origin:EZE").unwrap();
+    /// let flight = create!(Flight { origin: "EZE".to_string(), destination: "MAD".to_string(), }, &client).unwrap();
+    /// create!(Flight { origin: "EZE".to_string(), destination: "JFK".to_string(), }, &client).unwrap();
+    ///
+    /// let mut query = Query::<Flight>::find("origin", "EZE", &client);
+    /// query.diff("destination", "JFK");
+    /// let cached = query.store("cache:flights:eze", 60).unwrap();
+    /// assert_eq!(cached.ids().unwrap(), vec![flight.id]);
+    ///
+    /// let reused = Query::<Flight>::new(StalSet::Key(b"cache:flights:eze".to_vec()), &client);
+    /// assert_eq!(reused.ids().unwrap(), vec![flight.id]);
+    /// # }
+    /// ```
+    pub fn store(&self, name: &str, ttl: usize) -> Result<Query<'a, T>, OhmerError> {
+        let key = name.as_bytes().to_vec();
+        let template = vec![b"SUNIONSTORE".to_vec(), key.clone(), vec![]];
+        let stal = stal::Stal::from_template(template, vec![(self.set.clone(), 2)]);
+        let (mut ops, pos) = self.solve(stal);
+        if ttl > 0 {
+            let exec = ops.pop().unwrap();
+            ops.push(vec![b"EXPIRE".to_vec(), key.clone(), format!("{}", ttl).into_bytes()]);
+            ops.push(exec);
+        }
+        let _:usize = try!(run_ops((ops, pos), self.r));
+        Ok(Query::new(stal::Set::Key(key), self.r))
+    }
+
+    /// Creates an iterator for all objects in the set, loading only
+    /// `fields`. The other fields keep their default value.
+    pub fn select(&self, fields: &[&str]) -> Result<Iter<'a, T>, OhmerError> {
+        let mut iter = try!(self.try_iter());
+        iter.fields = Some(fields.iter().map(|f| f.to_string()).collect());
+        Ok(iter)
+    }
+
+    /// Gets the values of `fields` of all objects in the set, decoded as a
+    /// projection struct `P`. The values of every object are requested
+    /// in a single pipeline.
+    pub fn select_as<P: rustc_serialize::Decodable>(&self, fields: &[&str]) -> Result<Vec<P>, OhmerError> {
+        let ids:Vec<usize> = try!(run_ops(self.solve(self.set.ids()), self.r));
+        if ids.len() == 0 {
+            return Ok(vec![]);
+        }
+        let class_name = T::default().get_class_name();
+        let names = field_names(fields);
+        let mut q = redis::pipe();
+        for id in ids.iter() {
+            q.cmd("HMGET").arg(format!("{}:{}", class_name, id)).arg(&*names);
+        }
+        let values:Vec<Vec<Option<Vec<u8>>>> = try!(q.query(&traced(self.r)));
+        ids.into_iter().zip(values.into_iter()).map(|(id, values)| {
+            let mut decoder = Decoder::new(field_properties(id, &names, values));
+            Ok(try!(rustc_serialize::Decodable::decode(&mut decoder)))
+        }).collect()
     }
 
     /// Creates an iterator for all objects in the set, consuming the query.
-    pub fn try_into_iter(self) -> Result<Iter<'a, T>, OhmerError> {
-        Iter::from_ops(self.set.into_ids().solve(), self.r)
+    pub fn try_into_iter(mut self) -> Result<Iter<'a, T>, OhmerError> {
+        let stal = replace(&mut self.set, stal::Set::Key(vec![])).into_ids();
+        Iter::from_ops(self.solve(stal), self.r)
+    }
+
+    /// Creates an iterator that walks the set with SSCAN, loading `batch`
+    /// objects at a time. The query must be over a single key; compound
+    /// queries can be saved with `store` first.
+    pub fn scan(&self, batch: usize) -> Result<Scan<'a, T>, OhmerError> {
+        match self.set {
+            stal::Set::Key(ref key) if self.preps.len() == 0 => Scan::new(key.clone(), batch, self.r),
+            _ => Err(OhmerError::NotStored),
+        }
+    }
+
+    /// Deletes all objects in the set in the server, without loading them,
+    /// and returns how many were deleted. Ids without a stored object are
+    /// removed from the indices but not counted. If `soft_delete` is
+    /// enabled, they can be restored using `restore`.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{with, Ohmer, Query};
+    /// # use redis::Commands;
+    /// model!(
+    ///     Cookie {
+    ///         uniques { token:String = "".to_string(); };
+    ///         indices { browser:String = "".to_string(); };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// # let _:bool = client.del("Cookie:indices:browser:Firefox").unwrap();
+    /// # let _:bool = client.del("Cookie:uniques:token").unwrap();
+    /// create!(Cookie { token: "a1".to_string(), browser: "Firefox".to_string(), }, &client).unwrap();
+    /// create!(Cookie { token: "b2".to_string(), browser: "Firefox".to_string(), }, &client).unwrap();
+    /// create!(Cookie { token: "c3".to_string(), browser: "Chrome".to_string(), }, &client).unwrap();
+    ///
+    /// assert_eq!(Query::<Cookie>::find("browser", "Firefox", &client).delete_all().unwrap(), 2);
+    /// assert!(with::<Cookie, _>("token", "a1", &client).unwrap().is_none());
+    /// assert!(with::<Cookie, _>("token", "c3", &client).unwrap().is_some());
+    /// # }
+    /// ```
+    pub fn delete_all(&self) -> Result<usize, OhmerError> {
+        let obj = T::default();
+        let encoder = try!(obj.encoder());
+        let name = obj.get_class_name();
+        let mut template = vec![
+            b"EVAL".to_vec(),
+            DELETE_ALL.as_bytes().to_vec(),
+            b"1".to_vec(),
+            vec![],
+            name.as_bytes().to_vec(),
+            if obj.soft_delete() { b"soft".to_vec() } else { vec![] },
+        ];
+        for property in encoder.sets.iter().chain(encoder.lists.iter()) {
+            template.push(format!("{}:{}:*", name, property).into_bytes());
+        }
+        for counter in encoder.counters.iter() {
+            template.push(format!("{}:*:{}", name, counter).into_bytes());
+        }
+        let stal = stal::Stal::from_template(template, vec![(self.set.clone(), 3)]);
+        run_ops(self.solve(stal), self.r)
+    }
+
+    /// Sets `field` to `value` in all objects in the set in the server,
+    /// without loading them, and returns how many were updated. Indices,
+    /// uniques and `UpdatedAt` fields are updated as `save` does.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query};
+    /// # use redis::Commands;
+    /// model!(
+    ///     Plugin {
+    ///         indices {
+    ///             browser:String = "".to_string();
+    ///             enabled:bool = true;
+    ///         };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// # let _:bool = client.del("Plugin:indices:browser:Firefox").unwrap();
+    /// let plugin = create!(Plugin { browser: "Firefox".to_string(), }, &client).unwrap();
+    ///
+    /// assert_eq!(Query::<Plugin>::find("browser", "Firefox", &client).update_all("enabled", false).unwrap(), 1);
+    /// assert!(!ohmers::get::<Plugin>(plugin.id, &client).unwrap().enabled);
+    /// assert!(Query::<Plugin>::find("enabled", "0", &client).ids().unwrap().contains(&plugin.id));
+    /// assert!(!Query::<Plugin>::find("enabled", "1", &client).ids().unwrap().contains(&plugin.id));
+    /// # }
+    /// ```
+    pub fn update_all<S: rustc_serialize::Encodable>(&self, field: &str, value: S) -> Result<usize, OhmerError> {
+        let obj = T::default();
+        let encoder = try!(obj.encoder());
+        // references are indexed by their field name
+        let base = if field.len() > 3 && &field[field.len() - 3..] == "_id" { &field[..field.len() - 3] } else { field };
+        let attribute = encoder.attributes.chunks(2).any(|pair| &*pair[0] == field.as_bytes());
+        let composite = obj.composite_unique_fields().iter().chain(obj.composite_index_fields().iter())
+            .any(|fields| fields.contains(&field) || fields.contains(&base));
+        let geo = obj.geo_fields().iter().any(|&(_, lon, lat)| lon == field || lat == field);
+        // the value is stored as it would be by `save`
+        let mut value_encoder = Encoder::new();
+        try!(value.encode(&mut value_encoder));
+        let mut args = value_encoder.attributes;
+        if !attribute || field == "id" || is_timestamp(&encoder, field) || composite || geo || args.len() != 1 {
+            return Err(OhmerError::NotUpdatable(field.to_string()));
+        }
+        let value = args.pop().unwrap();
+
+        let mut update = HashMap::new();
+        let mut indices = vec![];
+        update.insert("name", obj.get_class_name());
+        update.insert("field", field.to_string());
+        let unique = obj.unique_fields().contains(field);
+        let text = obj.text_fields().contains(field);
+        let index = obj.index_fields().contains(field) || (base != field && obj.index_fields().contains(base));
+        if unique || text || index {
+            let normalized = obj.normalize(field, &*try!(String::from_utf8(value.clone())));
+            if text {
+                indices = obj.terms(field, &*normalized);
+            } else if index {
+                indices.push(normalized.clone());
+            }
+            if unique {
+                update.insert("unique", normalized);
+            }
+            if text || index {
+                update.insert("indexed", "1".to_string());
+            }
+        }
+        if obj.prefix_fields().contains(field) {
+            update.insert("prefix", "1".to_string());
+        }
+        if let Some(updated_at) = encoder.features.get("updated_at") {
+            update.insert("updated_at", updated_at.clone());
+            if sorted_fields(&obj, &encoder).contains(updated_at) {
+                update.insert("sorted", "1".to_string());
+            }
+        }
+
+        let template = vec![
+            b"EVAL".to_vec(),
+            UPDATE_ALL.as_bytes().to_vec(),
+            b"1".to_vec(),
+            vec![],
+            try!(msgpack_encode(&update)),
+            value,
+            try!(msgpack_encode(&indices)),
+        ];
+        let stal = stal::Stal::from_template(template, vec![(self.set.clone(), 3)]);
+        match run_ops(self.solve(stal), self.r) {
+            Err(OhmerError::RedisError(e)) => Err(save_error(&*format!("{}", e)).unwrap_or(OhmerError::RedisError(e))),
+            result => result,
+        }
+    }
+
+    /// Number of objects in the set, without loading them.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query};
+    /// # use redis::Commands;
+    /// model!(
+    ///     Order {
+    ///         indices { status:String = "".to_string(); };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// # let _:bool = client.del("Order:indices:status:pending").unwrap();
+    /// let order = create!(Order { status: "pending".to_string(), }, &client).unwrap();
+    /// create!(Order { status: "pending".to_string(), }, &client).unwrap();
+    /// let query = Query::<Order>::find("status", "pending", &client);
+    /// assert_eq!(query.count().unwrap(), 2);
+    /// assert!(!query.is_empty().unwrap());
+    /// assert_eq!(query.first().unwrap(), Some(order));
+    /// assert!(Query::<Order>::find("status", "lost", &client).is_empty().unwrap());
+    /// # }
+    /// ```
+    pub fn count(&self) -> Result<usize, OhmerError> {
+        let stal = stal::Stal::from_template(vec![b"SCARD".to_vec(), vec![]], vec![(self.set.clone(), 1)]);
+        run_ops(self.solve(stal), self.r)
+    }
+
+    /// Checks if there are no objects in the set.
+    pub fn is_empty(&self) -> Result<bool, OhmerError> {
+        Ok(try!(self.count()) == 0)
+    }
+
+    /// Gets the object with the lowest id in the set, if any.
+    pub fn first(&self) -> Result<Option<T>, OhmerError> {
+        let template = vec![b"SORT".to_vec(), vec![], b"LIMIT".to_vec(), b"0".to_vec(), b"1".to_vec()];
+        let stal = stal::Stal::from_template(template, vec![(self.set.clone(), 1)]);
+        let ids:Vec<usize> = try!(run_ops(self.solve(stal), self.r));
+        match ids.first() {
+            Some(id) => Ok(Some(try!(get(*id, self.r)))),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the ids of all objects in the set, in ascending order, without
+    /// loading them.
+    pub fn ids(&self) -> Result<Vec<usize>, OhmerError> {
+        let mut ids:Vec<usize> = try!(run_ops(self.solve(self.set.ids()), self.r));
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Creates an iterator of `n` random objects in the set. If `replace`
+    /// is true, the same object may be returned more than once, and `n`
+    /// objects are returned even if the set is smaller.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query};
+    /// # use redis::Commands;
+    /// model!(
+    ///     Variant {
+    ///         indices { experiment:String = "".to_string(); };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// # let _:bool = client.del("Variant:indices:experiment:checkout").unwrap();
+    /// for _ in 0..3 {
+    ///     create!(Variant { experiment: "checkout".to_string(), }, &client).unwrap();
+    /// }
+    /// let query = Query::<Variant>::find("experiment", "checkout", &client);
+    /// assert_eq!(query.sample(2, false).unwrap().count(), 2);
+    /// assert_eq!(query.sample(5, false).unwrap().count(), 3);
+    /// assert_eq!(query.sample(5, true).unwrap().count(), 5);
+    /// # }
+    /// ```
+    pub fn sample(&self, n: usize, replace: bool) -> Result<Iter<'a, T>, OhmerError> {
+        let count = if replace { -(n as isize) } else { n as isize };
+        let template = vec![b"SRANDMEMBER".to_vec(), vec![], format!("{}", count).into_bytes()];
+        let stal = stal::Stal::from_template(template, vec![(self.set.clone(), 1)]);
+        Iter::from_ops(self.solve(stal), self.r)
     }
 
     /// Creates an iterator for all objects in the set sorted by `by`.
     pub fn sort(&self, by: &str, limit: Option<(usize, usize)>, asc: bool, alpha: bool) -> Result<Iter<'a, T>, OhmerError> {
+        let mut sort = self.sort_by(by, asc, alpha);
+        if let Some(l) = limit {
+            sort.limit(l.0, l.1);
+        }
+        sort.try_iter()
+    }
+
+    /// Creates a builder to sort the set by `by`, and then by more keys.
+    /// Counters and regular fields can be used interchangeably. A single
+    /// key is sorted with SORT, while several keys are sorted by a script
+    /// that loads the values of the whole set.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create, incr)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Counter, Ohmer, Query};
+    /// # use redis::Commands;
+    /// model!(
+    ///     Player {
+    ///         indices { team:String = "".to_string(); };
+    ///         name:String = "".to_string();
+    ///         level:u8 = 0;
+    ///         wins:Counter = Counter;
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// # let _:bool = client.del("Player:indices:team:red").unwrap();
+    /// let ann = create!(Player { team: "red".to_string(), name: "Ann".to_string(), level: 3, }, &client).unwrap();
+    /// let bob = create!(Player { team: "red".to_string(), name: "Bob".to_string(), level: 5, }, &client).unwrap();
+    /// let cid = create!(Player { team: "red".to_string(), name: "Cid".to_string(), level: 3, }, &client).unwrap();
+    /// incr!(cid.wins, 2, &client).unwrap();
+    ///
+    /// let query = Query::<Player>::find("team", "red", &client);
+    /// let players = query.sort_by("level", false, false).then_by("wins", false, false)
+    ///     .try_iter().unwrap().collect::<Vec<_>>();
+    /// assert_eq!(players, vec![bob, cid, ann]);
+    ///
+    /// let names:Vec<(String, u8)> = query.sort_by("name", true, true).limit(0, 2).rows(&["name", "level"]).unwrap();
+    /// assert_eq!(names, vec![("Ann".to_string(), 3), ("Bob".to_string(), 5)]);
+    /// # }
+    /// ```
+    pub fn sort_by<'q>(&'q self, by: &str, asc: bool, alpha: bool) -> Sort<'q, 'a, T> {
+        Sort { query: self, keys: vec![(Query::<T>::pattern(by), asc, alpha)], limit: None }
+    }
+
+    /// Pattern used by SORT to get the value of `field` for each element.
+    /// The `id` field is the element itself.
+    fn pattern(field: &str) -> Vec<u8> {
         let default = T::default();
         let class_name = default.get_class_name();
-        let key = if default.counters().contains(by) {
-            format!("{}:*:{}", class_name, by)
+        if field == "id" {
+            b"#".to_vec()
+        } else if default.counters().contains(field) {
+            format!("{}:*:{}", class_name, field).into_bytes()
         } else {
-            format!("{}:*->{}", class_name, by)
-        }.as_bytes().to_vec();
+            format!("{}:*->{}", class_name, field).into_bytes()
+        }
+    }
 
-        let mut template = vec![b"SORT".to_vec(), vec![], b"BY".to_vec(), key];
-        if let Some(l) = limit {
-            template.push(b"LIMIT".to_vec());
-            template.push(format!("{}", l.0).as_bytes().to_vec());
-            template.push(format!("{}", l.1).as_bytes().to_vec());
+    /// Gets up to `size` objects in the set, ordered by id, after `cursor`.
+    /// The first page is requested without a cursor, and each page has the
+    /// cursor of the next one, failing with `InvalidCursor` for any other
+    /// value. Each page reads every id in the set in a single script, so it
+    /// takes O(N) for a set of N elements, blocking the server meanwhile.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # #[macro_use(model, create)] extern crate ohmers;
+    /// # extern crate rustc_serialize;
+    /// # extern crate redis;
+    /// # use ohmers::{Ohmer, Query};
+    /// # use redis::Commands;
+    /// model!(
+    ///     Ticket {
+    ///         indices { queue:String = "".to_string(); };
+    ///     });
+    /// # fn main() {
+    /// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    /// # let _:bool = client.del("Ticket:indices:queue:support").unwrap();
+    /// for _ in 0..5 {
+    ///     create!(Ticket { queue: "support".to_string(), }, &client).unwrap();
+    /// }
+    /// let query = Query::<Ticket>::find("queue", "support", &client);
+    /// let page = query.page(None, 3).unwrap();
+    /// assert_eq!(page.items.len(), 3);
+    /// let page = query.page(page.next.as_ref().map(|c| &**c), 3).unwrap();
+    /// assert_eq!(page.items.len(), 2);
+    /// assert_eq!(page.next, None);
+    /// # }
+    /// ```
+    pub fn page(&self, cursor: Option<&str>, size: usize) -> Result<Page<T>, OhmerError> {
+        self.run_page(vec![], cursor, size)
+    }
+
+    /// Gets up to `size` objects in the set sorted by `by`, after `cursor`.
+    /// Objects with the same value are ordered by id. As with `page`, each
+    /// page reads the value of every element of the set. Indexed timestamps
+    /// are paged through their sorted set instead, reading its elements
+    /// from the cursor until the page is full; if few of them are in the
+    /// set, that may still read most of the sorted set.
+    pub fn sort_page(&self, by: &str, cursor: Option<&str>, size: usize, asc: bool, alpha: bool) -> Result<Page<T>, OhmerError> {
+        let obj = T::default();
+        let encoder = try!(obj.encoder());
+        let sorted = if !alpha && sorted_fields(&obj, &encoder).iter().any(|field| field == by) {
+            obj.key_for_sorted(by).into_bytes()
+        } else {
+            vec![]
+        };
+        let key = Query::<T>::pattern(by);
+        let order = if asc { b"ASC".to_vec() } else { b"DESC".to_vec() };
+        let alpha = if alpha { b"ALPHA".to_vec() } else { vec![] };
+        self.run_page(vec![key, order, alpha, sorted], cursor, size)
+    }
+
+    /// Runs the page script with the sorting arguments `args`.
+    fn run_page(&self, args: Vec<Vec<u8>>, cursor: Option<&str>, size: usize) -> Result<Page<T>, OhmerError> {
+        if let Some(cursor) = cursor {
+            // the value is a score if the page is read from a sorted set
+            let score = args.get(3).map(|sorted| sorted.len() > 0).unwrap_or(false);
+            if !valid_cursor(cursor, score) {
+                return Err(OhmerError::InvalidCursor(cursor.to_string()));
+            }
         }
-        template.push(if asc { b"ASC".to_vec() } else { b"DESC".to_vec() });
-        if alpha {
-            template.push(b"ALPHA".to_vec());
+        let mut template = vec![
+            b"EVAL".to_vec(),
+            PAGE.as_bytes().to_vec(),
+            b"1".to_vec(),
+            vec![],
+            cursor.unwrap_or("").as_bytes().to_vec(),
+            format!("{}", size).as_bytes().to_vec(),
+        ];
+        template.extend(args);
+
+        let stal = stal::Stal::from_template(template, vec![(self.set.clone(), 3)]);
+        let (ids, next):(Vec<usize>, String) = try!(run_ops(self.solve(stal), self.r));
+        let mut items = Vec::with_capacity(ids.len());
+        for id in ids {
+            items.push(try!(get(id, self.r)));
         }
+        Ok(Page { items: items, next: if next.len() > 0 { Some(next) } else { None } })
+    }
+}
 
-        let stal = stal::Stal::from_template(template, vec![(self.set.clone(), 1)]);
-        Iter::from_ops(stal.solve(), self.r)
+/// Checks that a page cursor has an id and a value separated by a colon, as
+/// the page script returns it.
+fn valid_cursor(cursor: &str, score: bool) -> bool {
+    let mut parts = cursor.splitn(2, ':');
+    let id = parts.next().map(|id| id.len() > 0 && id.chars().all(|c| c.is_digit(10))).unwrap_or(false);
+    match parts.next() {
+        Some(value) => id && (!score || value.parse::<f64>().is_ok()),
+        None => false,
+    }
+}
+
+/// Builder to sort a query by several keys, created by `Query::sort_by`.
+pub struct Sort<'q, 'a: 'q, T: 'a + Ohmer> {
+    query: &'q Query<'a, T>,
+    /// Pattern, ascending order and lexicographic comparison of each key.
+    keys: Vec<(Vec<u8>, bool, bool)>,
+    limit: Option<(usize, usize)>,
+}
+
+impl<'q, 'a, T: Ohmer> Sort<'q, 'a, T> {
+    /// Sorts the elements with the same value in the previous keys by `by`.
+    pub fn then_by(&mut self, by: &str, asc: bool, alpha: bool) -> &mut Self {
+        self.keys.push((Query::<T>::pattern(by), asc, alpha));
+        self
+    }
+
+    /// Gets only `count` elements, skipping the first `offset`.
+    pub fn limit(&mut self, offset: usize, count: usize) -> &mut Self {
+        self.limit = Some((offset, count));
+        self
+    }
+
+    /// Creates an iterator for the sorted objects.
+    pub fn try_iter(&self) -> Result<Iter<'a, T>, OhmerError> {
+        Iter::from_ops(self.query.solve(self.stal(vec![])), self.query.r)
+    }
+
+    /// Lists the commands `try_iter` sends to sort the set.
+    pub fn explain(&self) -> Vec<String> {
+        self.query.solve(self.stal(vec![])).0.iter().map(|op| trace::format_command(op)).collect()
+    }
+
+    /// Gets the values of `fields` for each sorted element, without loading
+    /// the objects. Each row is converted to `R`, usually a tuple.
+    /// The `id` field gets the id of each element. Without fields, no rows
+    /// are returned.
+    pub fn rows<R: redis::FromRedisValue>(&self, fields: &[&str]) -> Result<Vec<R>, OhmerError> {
+        if fields.len() == 0 {
+            return Ok(vec![]);
+        }
+        let gets = fields.iter().map(|field| Query::<T>::pattern(field)).collect();
+        let values:Vec<redis::Value> = try!(run_ops(self.query.solve(self.stal(gets)), self.query.r));
+        let mut rows = Vec::with_capacity(values.len() / fields.len());
+        for row in values.chunks(fields.len()) {
+            rows.push(try!(R::from_redis_value(&redis::Value::Bulk(row.to_vec()))));
+        }
+        Ok(rows)
+    }
+
+    /// Creates the operations to sort, using SORT for a single key and a
+    /// script for several.
+    fn stal(&self, gets: Vec<Vec<u8>>) -> stal::Stal {
+        let mut template;
+        if self.keys.len() == 1 {
+            let (ref key, asc, alpha) = self.keys[0];
+            template = vec![b"SORT".to_vec(), vec![]];
+            if &**key != b"#" {
+                // without BY, the ids are sorted by themselves
+                template.push(b"BY".to_vec());
+                template.push(key.clone());
+            }
+            if let Some(l) = self.limit {
+                template.push(b"LIMIT".to_vec());
+                template.push(format!("{}", l.0).as_bytes().to_vec());
+                template.push(format!("{}", l.1).as_bytes().to_vec());
+            }
+            template.push(if asc { b"ASC".to_vec() } else { b"DESC".to_vec() });
+            if alpha {
+                template.push(b"ALPHA".to_vec());
+            }
+            for get in gets {
+                template.push(b"GET".to_vec());
+                template.push(get);
+            }
+        } else {
+            let (offset, count) = match self.limit {
+                Some((offset, count)) => (format!("{}", offset), format!("{}", count)),
+                None => ("0".to_string(), "-1".to_string()),
+            };
+            template = vec![
+                b"EVAL".to_vec(),
+                MULTI_SORT.as_bytes().to_vec(),
+                b"1".to_vec(),
+                vec![],
+                offset.into_bytes(),
+                count.into_bytes(),
+                format!("{}", self.keys.len()).into_bytes(),
+            ];
+            for &(ref key, asc, alpha) in self.keys.iter() {
+                template.push(key.clone());
+                template.push(if asc { b"ASC".to_vec() } else { b"DESC".to_vec() });
+                template.push(if alpha { b"ALPHA".to_vec() } else { vec![] });
+            }
+            template.extend(gets);
+        }
+        let pos = if self.keys.len() == 1 { 1 } else { 3 };
+        stal::Stal::from_template(template, vec![(self.query.set.clone(), pos)])
+    }
+}
+
+/// A page of query results.
+#[derive(Debug)]
+pub struct Page<T> {
+    /// Objects in the page.
+    pub items: Vec<T>,
+    /// Cursor to get the next page, if there are more objects.
+    pub next: Option<String>,
+}
+
+/// Runs a list of operations wrapped in a MULTI/EXEC, returning the result
+/// of the operation at the given position.
+fn run_ops<R: redis::FromRedisValue>(ops: (Vec<Vec<Vec<u8>>>, usize), r: &redis::ConnectionLike) -> Result<R, OhmerError> {
+    let mut q = redis::pipe();
+    q.atomic();
+    let mut i = 0;
+    let len = ops.0.len();
+
+    for op in ops.0.into_iter() {
+        if i == 0 || i == len - 1 {
+            i += 1;
+            // skip MULTI and EXEC
+            continue;
+        }
+        let mut first = true;
+        for arg in op {
+            if first {
+                q.cmd(&*try!(String::from_utf8(arg)));
+                first = false;
+            } else {
+                q.arg(arg);
+            }
+            if i != ops.1 {
+                q.ignore();
+            }
+        }
+        i += 1;
     }
+    let mut result:Vec<redis::Value> = try!(q.query(&traced(r)));
+    Ok(try!(R::from_redis_value(&result.pop().unwrap())))
 }
 
 /// Iterator for query results
 pub struct Iter<'a, T> {
-    r: &'a redis::Client,
+    r: &'a redis::ConnectionLike,
     iter: std::vec::IntoIter<usize>,
     phantom: PhantomData<T>,
+    /// Fields to load, or all of them if `None`.
+    fields: Option<Vec<String>>,
 }
 
 impl<'a, T: Ohmer> Iter<'a, T> {
     /// Creates a new iterator from a list of ids
-    fn new(iter: std::vec::IntoIter<usize>, r: &'a redis::Client) -> Self {
+    fn new(iter: std::vec::IntoIter<usize>, r: &'a redis::ConnectionLike) -> Self {
         Iter {
             iter: iter,
             r: r,
             phantom: PhantomData,
+            fields: None,
         }
     }
 
     /// Creates an iterator from a list of operations. The operations must
     /// be wrapped in a MULTI/EXEC, and it is required to provide which
     /// operation returns the list of ids.
-    fn from_ops(ops: (Vec<Vec<Vec<u8>>>, usize), r: &'a redis::Client) -> Result<Self, OhmerError> {
-        let mut q = redis::pipe();
-        q.atomic();
-        let mut i = 0;
-        let len = ops.0.len();
-
-        for op in ops.0.into_iter() {
-            if i == 0 || i == len - 1 {
-                i += 1;
-                // skip MULTI and EXEC
-                continue;
-            }
-            let mut first = true;
-            for arg in op {
-                if first {
-                    q.cmd(&*try!(String::from_utf8(arg)));
-                    first = false;
-                } else {
-                    q.arg(arg);
-                }
-                if i != ops.1 {
-                    q.ignore();
-                }
-            }
-            i += 1;
-        }
-        let mut result:Vec<Vec<usize>> = try!(q.query(r));
-        Ok(Iter { iter: result.pop().unwrap().into_iter(), r: r, phantom: PhantomData })
+    fn from_ops(ops: (Vec<Vec<Vec<u8>>>, usize), r: &'a redis::ConnectionLike) -> Result<Self, OhmerError> {
+        let ids:Vec<usize> = try!(run_ops(ops, r));
+        Ok(Iter::new(ids.into_iter(), r))
     }
 }
 
@@ -1457,16 +3639,111 @@ impl<'a, T: Ohmer> Iterator for Iter<'a, T> {
     type Item = T;
 
     fn next(&mut self) -> Option<T> {
-        match self.iter.next() {
-            Some(id) => match get(id, self.r) {
-                Ok(v) => Some(v),
-                Err(_) => None,
+        let id = match self.iter.next() {
+            Some(id) => id,
+            None => return None,
+        };
+        let obj = match self.fields {
+            Some(ref fields) => {
+                let fields = fields.iter().map(|f| &**f).collect::<Vec<_>>();
+                get_fields(id, &*fields, self.r)
             },
-            None => None,
-        }
+            None => get(id, self.r).map_err(OhmerError::from),
+        };
+        obj.ok()
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
         (self.iter.len(), Some(self.iter.len()))
     }
 }
+
+/// Iterator that walks a set with SSCAN, loading the objects of each batch
+/// in a single request. Only the current batch is kept in memory, so SSCAN
+/// may return an id again, for instance if the set grows during the scan,
+/// and its object is then returned again. An object that cannot be decoded
+/// is returned as an error and the scan goes on, while a failed request
+/// ends it with its error.
+pub struct Scan<'a, T> {
+    r: &'a redis::ConnectionLike,
+    key: Vec<u8>,
+    batch: usize,
+    /// Cursor of the next SSCAN, or `None` after the last one.
+    cursor: Option<u64>,
+    /// Objects of the current batch, or their decoding errors, by id.
+    objects: std::vec::IntoIter<(usize, Result<T, OhmerError>)>,
+}
+
+impl<'a, T: Ohmer> Scan<'a, T> {
+    /// Creates a new iterator over the set `key`, failing if `batch` is
+    /// zero.
+    fn new(key: Vec<u8>, batch: usize, r: &'a redis::ConnectionLike) -> Result<Self, OhmerError> {
+        if batch == 0 {
+            return Err(OhmerError::RedisError(redis::RedisError::from((redis::ErrorKind::InvalidClientConfig, "Scan batch must be positive"))));
+        }
+        Ok(Scan {
+            r: r,
+            key: key,
+            batch: batch,
+            cursor: Some(0),
+            objects: vec![].into_iter(),
+        })
+    }
+
+    /// Loads the objects of the next batch. SSCAN may return no ids even
+    /// if there are more batches.
+    fn fetch(&mut self) -> Result<(), OhmerError> {
+        let cursor = match self.cursor {
+            Some(cursor) => cursor,
+            None => return Ok(()),
+        };
+        let (next, ids):(u64, Vec<usize>) = try!(redis::cmd("SSCAN")
+                .arg(&*self.key)
+                .arg(cursor)
+                .arg("COUNT")
+                .arg(self.batch)
+                .query(&traced(self.r)));
+        self.cursor = if next == 0 { None } else { Some(next) };
+
+        let class_name = T::default().get_class_name();
+        let mut q = redis::pipe();
+        for id in ids.iter() {
+            q.cmd("HGETALL").arg(format!("{}:{}", class_name, id));
+        }
+        let hashes:Vec<HashMap<String, Vec<u8>>> = if ids.len() > 0 {
+            try!(q.query(&traced(self.r)))
+        } else {
+            vec![]
+        };
+
+        let mut objects = Vec::with_capacity(ids.len());
+        for (id, properties) in ids.into_iter().zip(hashes.into_iter()) {
+            if properties.len() == 0 {
+                // deleted after the scan
+                continue;
+            }
+            objects.push((id, decode_hash(id, properties).map_err(OhmerError::from)));
+        }
+        self.objects = objects.into_iter();
+        Ok(())
+    }
+}
+
+impl<'a, T: Ohmer> Iterator for Scan<'a, T> {
+    type Item = Result<T, OhmerError>;
+
+    fn next(&mut self) -> Option<Result<T, OhmerError>> {
+        loop {
+            if let Some((_, obj)) = self.objects.next() {
+                return Some(obj);
+            }
+            if self.cursor.is_none() {
+                return None;
+            }
+            if let Err(e) = self.fetch() {
+                self.cursor = None;
+                return Some(Err(e));
+            }
+        }
+    }
+}
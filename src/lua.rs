@@ -1,6 +1,6 @@
 // Taken from https://raw.githubusercontent.com/soveran/ohm/2.3.0/lib/ohm/lua/save.lua
 pub const SAVE:&'static str = "
--- This script receives four parameters, all encoded with
+-- This script receives seven parameters, all encoded with
 -- MessagePack. The decoded values are used for saving a model
 -- instance in Redis, creating or updating a hash as needed and
 -- updating zero or more sets (indices) and zero or more hashes
@@ -11,6 +11,8 @@ pub const SAVE:&'static str = "
 -- Table with one or two attributes:
 --    name (model name)
 --    id (model instance id, optional)
+--    created_at (attribute set on creation, optional)
+--    updated_at (attribute set on every save, optional)
 --
 -- If the id is not provided, it is treated as a new record.
 --
@@ -32,10 +34,76 @@ pub const SAVE:&'static str = "
 -- value), an error is returned with the UniqueIndexViolation
 -- message and the field that triggered the error.
 --
+-- # sorted
+--
+-- Fields whose numeric values are indexed in a sorted set, so
+-- they can be queried by range.
+--
+-- # prefixes
+--
+-- Fields whose values are indexed in a lexicographic sorted set,
+-- as `value:id` members, so they can be queried by prefix.
+--
+-- # geo
+--
+-- Geo indices, each mapped to the longitude and latitude fields
+-- whose values locate the model instance. If a coordinate is out
+-- of range, an error is returned with the InvalidCoordinates
+-- message and the geo index that triggered the error.
+--
+-- The script returns the id, the server time used for the
+-- timestamps and the creation time, kept from the stored hash on
+-- updates, or 0 if the model has no timestamps.
+--
+-- If an eighth parameter is `verify`, the uniques and the geo
+-- coordinates are checked, but nothing is saved.
+--
+-- If it is `reindex`, the hash and its timestamps are left as they
+-- are, and only the indices, uniques, sorted sets and geo indices
+-- are rewritten. Unique values held by another existing instance
+-- are skipped, and their fields are returned after the id and the
+-- times.
+--
+-- A ninth parameter sets the instance to expire in that many
+-- seconds, along with the keys in the tenth parameter, encoded
+-- with MessagePack, where `*` stands for the id.
+--
 local model   = cmsgpack.unpack(ARGV[1])
 local attrs   = cmsgpack.unpack(ARGV[2])
 local indices = cmsgpack.unpack(ARGV[3])
 local uniques = cmsgpack.unpack(ARGV[4])
+local sorted  = cmsgpack.unpack(ARGV[5])
+local prefixes = cmsgpack.unpack(ARGV[6])
+local geo     = cmsgpack.unpack(ARGV[7])
+
+local function stamp(model, attrs)
+	if model.created_at == nil and model.updated_at == nil then
+		return 0, 0
+	end
+
+	-- TIME is not deterministic, replicate the effects instead
+	if redis.replicate_commands then
+		redis.replicate_commands()
+	end
+
+	local now = redis.call(\"TIME\")[1]
+	local created = now
+
+	-- an update keeps the stored creation time
+	if model.id ~= nil and model.created_at ~= nil then
+		created = redis.call(\"HGET\", model.name .. \":\" .. model.id, model.created_at) or now
+	end
+
+	for i = 1, #attrs, 2 do
+		if attrs[i] == model.updated_at then
+			attrs[i + 1] = now
+		elseif attrs[i] == model.created_at then
+			attrs[i + 1] = created
+		end
+	end
+
+	return now, created
+end
 
 local function save(model, attrs)
 	if model.id == nil then
@@ -45,6 +113,7 @@ local function save(model, attrs)
 	model.key = model.name .. \":\" .. model.id
 
 	redis.call(\"SADD\", model.name .. \":all\", model.id)
+	redis.call(\"SREM\", model.name .. \":deleted\", model.id)
 	redis.call(\"DEL\", model.key)
 
 	if math.mod(#attrs, 2) == 1 then
@@ -54,6 +123,13 @@ local function save(model, attrs)
 	if #attrs > 0 then
 		redis.call(\"HMSET\", model.key, unpack(attrs))
 	end
+
+	-- keep the expiration of the object, if any
+	local expires = redis.call(\"ZSCORE\", model.name .. \":expires\", model.id)
+
+	if expires then
+		redis.call(\"PEXPIREAT\", model.key, expires)
+	end
 end
 
 local function index(model, indices)
@@ -90,11 +166,104 @@ local function remove_uniques(model)
 	local memo = model.key .. \":_uniques\"
 
 	for _, key in pairs(redis.call(\"HKEYS\", memo)) do
-		redis.call(\"HDEL\", key, redis.call(\"HGET\", memo, key))
+		local value = redis.call(\"HGET\", memo, key)
+
+		-- the value may be held by another instance after a conflict
+		if redis.call(\"HGET\", key, value) == tostring(model.id) then
+			redis.call(\"HDEL\", key, value)
+		end
+
+		redis.call(\"HDEL\", memo, key)
+	end
+end
+
+local function sort(model, attrs, sorted)
+	local memo = model.key .. \":_sorted\"
+
+	for _, field in ipairs(sorted) do
+		for i = 1, #attrs, 2 do
+			if attrs[i] == field then
+				local key = model.name .. \":sorted:\" .. field
+
+				redis.call(\"HSET\", memo, key, model.id)
+				redis.call(\"ZADD\", key, attrs[i + 1], model.id)
+			end
+		end
+	end
+end
+
+local function remove_sorted(model)
+	local memo = model.key .. \":_sorted\"
+
+	for _, key in ipairs(redis.call(\"HKEYS\", memo)) do
+		redis.call(\"ZREM\", key, redis.call(\"HGET\", memo, key))
 		redis.call(\"HDEL\", memo, key)
 	end
 end
 
+local function prefix(model, attrs, prefixes)
+	local memo = model.key .. \":_sorted\"
+
+	for _, field in ipairs(prefixes) do
+		for i = 1, #attrs, 2 do
+			if attrs[i] == field then
+				local key = model.name .. \":prefix:\" .. field
+				local member = attrs[i + 1] .. \":\" .. model.id
+
+				redis.call(\"HSET\", memo, key, member)
+				redis.call(\"ZADD\", key, 0, member)
+			end
+		end
+	end
+end
+
+local function coordinates(attrs, fields)
+	local lon, lat
+
+	for i = 1, #attrs, 2 do
+		if attrs[i] == fields[1] then
+			lon = attrs[i + 1]
+		elseif attrs[i] == fields[2] then
+			lat = attrs[i + 1]
+		end
+	end
+
+	return lon, lat
+end
+
+local function locate(model, attrs, geo)
+	local memo = model.key .. \":_sorted\"
+
+	for name, fields in pairs(geo) do
+		local lon, lat = coordinates(attrs, fields)
+
+		if lon and lat then
+			local key = model.name .. \":geo:\" .. name
+
+			redis.call(\"HSET\", memo, key, model.id)
+			redis.call(\"GEOADD\", key, lon, lat, model.id)
+		end
+	end
+end
+
+local function verify_geo(attrs, geo)
+	for name, fields in pairs(geo) do
+		local lon, lat = coordinates(attrs, fields)
+
+		if lon and lat then
+			lon = tonumber(lon)
+			lat = tonumber(lat)
+
+			if not lon or not lat or lon < -180 or lon > 180 or
+				lat < -85.05112878 or lat > 85.05112878 then
+				return name
+			end
+		end
+	end
+end
+
+local reindex = ARGV[8] == \"reindex\"
+
 local function verify(model, uniques)
 	local duplicates = {}
 
@@ -102,7 +271,9 @@ local function verify(model, uniques)
 		local key = model.name .. \":uniques:\" .. field
 		local id = redis.call(\"HGET\", key, tostring(value))
 
-		if id and id ~= tostring(model.id) then
+		-- when reindexing, values held by missing instances are stale
+		if id and id ~= tostring(model.id) and
+			(not reindex or redis.call(\"EXISTS\", model.name .. \":\" .. id) == 1) then
 			duplicates[#duplicates + 1] = field
 		end
 	end
@@ -112,11 +283,64 @@ end
 
 local duplicates, err = verify(model, uniques)
 
-if err then
+if err and not reindex then
 	error(\"UniqueIndexViolation: \" .. duplicates[1])
 end
 
-save(model, attrs)
+local invalid = verify_geo(attrs, geo)
+
+if invalid then
+	error(\"InvalidCoordinates: \" .. invalid)
+end
+
+if ARGV[8] == \"verify\" then
+	return { tostring(model.id), \"0\" }
+end
+
+local function expiration(ttl)
+	-- TIME is not deterministic, replicate the effects instead
+	if redis.replicate_commands then
+		redis.replicate_commands()
+	end
+
+	local time = redis.call(\"TIME\")
+
+	return tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000) + ttl * 1000
+end
+
+local function expire(model, expires, tracked)
+	redis.call(\"PEXPIREAT\", model.key, expires)
+
+	for _, key in ipairs(tracked) do
+		redis.call(\"PEXPIREAT\", (string.gsub(key, \"%*\", tostring(model.id))), expires)
+	end
+
+	redis.call(\"ZADD\", model.name .. \":expires\", expires, model.id)
+end
+
+local expires
+
+if ARGV[9] then
+	expires = expiration(tonumber(ARGV[9]))
+end
+
+local now, created = 0, 0
+
+if reindex then
+	model.key = model.name .. \":\" .. model.id
+
+	for _, field in ipairs(duplicates) do
+		uniques[field] = nil
+	end
+else
+	now, created = stamp(model, attrs)
+
+	save(model, attrs)
+
+	if expires then
+		expire(model, expires, cmsgpack.unpack(ARGV[10]))
+	end
+end
 
 remove_indices(model)
 index(model, indices)
@@ -124,7 +348,151 @@ index(model, indices)
 remove_uniques(model, uniques)
 unique(model, uniques)
 
-return tostring(model.id)
+remove_sorted(model)
+sort(model, attrs, sorted)
+prefix(model, attrs, prefixes)
+locate(model, attrs, geo)
+
+if reindex then
+	return { tostring(model.id), tostring(now), tostring(created), unpack(duplicates) }
+end
+
+return { tostring(model.id), tostring(now), tostring(created) }
+";
+
+// Used after the SAVE script, wrapped in a `save` function taking its
+// arguments, to save several objects at once.
+pub const SAVE_MANY:&'static str = "
+-- Saves several objects, calling `save` with the arguments of each
+-- one.
+--
+-- ARGV[1] is the number of arguments of each object, and ARGV[2]
+-- is `abort` to save none of the objects if any of them has a
+-- unique value already in use, even by another object in the
+-- batch, or invalid coordinates. The arguments of the objects
+-- follow, in the order expected by SAVE.
+--
+-- The script returns, for each object, its id, the server time
+-- used for the timestamps and the creation time, or 0 and the
+-- error that prevented saving it.
+--
+local size    = tonumber(ARGV[1])
+local abort   = ARGV[2] == \"abort\"
+local objects = {}
+
+for i = 3, #ARGV, size do
+	objects[#objects + 1] = { unpack(ARGV, i, i + size - 1) }
+end
+
+local function message(err)
+	if type(err) == \"table\" then
+		return err.err
+	end
+
+	return tostring(err)
+end
+
+local function verify(objects)
+	local results = {}
+	local seen    = {}
+	local failed  = false
+
+	for i, args in ipairs(objects) do
+		local verifying = { unpack(args) }
+
+		verifying[size + 1] = \"verify\"
+
+		local ok, err = pcall(save, verifying)
+
+		results[i] = { \"0\", \"\" }
+
+		if not ok then
+			results[i][2] = message(err)
+			failed = true
+		else
+			for field, value in pairs(cmsgpack.unpack(args[4])) do
+				local key = field .. \":\" .. tostring(value)
+
+				if seen[key] then
+					results[i][2] = \"UniqueIndexViolation: \" .. field
+					failed = true
+				end
+
+				seen[key] = true
+			end
+		end
+	end
+
+	return results, failed
+end
+
+if abort then
+	local results, failed = verify(objects)
+
+	if failed then
+		return results
+	end
+end
+
+local results = {}
+
+for i, args in ipairs(objects) do
+	local ok, result = pcall(save, args)
+
+	if ok then
+		results[i] = result
+	else
+		results[i] = { \"0\", message(result) }
+	end
+end
+
+return results
+";
+
+// Used after the SAVE script, wrapped in a `save` function taking its
+// arguments, to rebuild the indices of several objects at once.
+pub const REINDEX:&'static str = "
+-- Rewrites the indices, uniques, sorted sets and geo indices of
+-- several objects, calling `save` in `reindex` mode with the
+-- arguments of each one.
+--
+-- ARGV[1] is the number of arguments of each object, and the
+-- arguments of the objects follow, in the order expected by SAVE.
+--
+-- The script returns the conflicts found, each one as the id of
+-- the object and the error: unique values already held by another
+-- instance, or invalid coordinates.
+--
+local size      = tonumber(ARGV[1])
+local conflicts = {}
+
+local function message(err)
+	if type(err) == \"table\" then
+		return err.err
+	end
+
+	return tostring(err)
+end
+
+for i = 2, #ARGV, size do
+	local args = { unpack(ARGV, i, i + size - 1) }
+
+	args[size + 1] = \"reindex\"
+
+	local ok, result = pcall(save, args)
+
+	if ok then
+		for j = 4, #result do
+			conflicts[#conflicts + 1] = { result[1], \"UniqueIndexViolation: \" .. result[j] }
+		end
+	else
+		local id = cmsgpack.unpack(args[1]).id
+
+		conflicts[#conflicts + 1] = { tostring(id), message(result) }
+	end
+end
+
+return conflicts
 ";
 
 // Taken from https://raw.githubusercontent.com/soveran/ohm/2.3.0/lib/ohm/lua/delete.lua
@@ -169,9 +537,21 @@ local function remove_uniques(model, uniques)
 
 	for field, _ in pairs(uniques) do
 		local key = model.name .. \":uniques:\" .. field
+		local value = redis.call(\"HGET\", memo, key)
 
-		redis.call(\"HDEL\", key, redis.call(\"HGET\", memo, key))
-		redis.call(\"HDEL\", memo, key)
+		-- soft deleted instances have no uniques
+		if value then
+			redis.call(\"HDEL\", key, value)
+			redis.call(\"HDEL\", memo, key)
+		end
+	end
+end
+
+local function remove_sorted(model)
+	local memo = model.key .. \":_sorted\"
+
+	for _, key in ipairs(redis.call(\"HKEYS\", memo)) do
+		redis.call(\"ZREM\", key, redis.call(\"HGET\", memo, key))
 	end
 end
 
@@ -188,17 +568,1081 @@ local function delete(model)
 		model.key .. \":counters\",
 		model.key .. \":_indices\",
 		model.key .. \":_uniques\",
+		model.key .. \":_sorted\",
 		model.key
 	}
 
 	redis.call(\"SREM\", model.name .. \":all\", model.id)
+	redis.call(\"SREM\", model.name .. \":deleted\", model.id)
+	redis.call(\"ZREM\", model.name .. \":expires\", model.id)
 	redis.call(\"DEL\", unpack(keys))
 end
 
 remove_indices(model)
 remove_uniques(model, uniques)
+remove_sorted(model)
 remove_tracked(model, tracked)
 delete(model)
 
 return model.id
 ";
+
+// Used by queries to combine sorted sets with other sets.
+pub const RANGE_STORE:&'static str = "
+-- Stores the members returned by a command in a set, so it can
+-- be used in set operations.
+--
+-- KEYS[1] is the destination set, and ARGV the command with its
+-- arguments, usually a range in a sorted set. Members may be
+-- `value:id` pairs, in which case only the id is stored.
+--
+local members = redis.call(unpack(ARGV))
+
+for i, member in ipairs(members) do
+	members[i] = string.match(member, \"([^:]*)$\")
+end
+
+redis.call(\"DEL\", KEYS[1])
+
+for i = 1, #members, 1000 do
+	redis.call(\"SADD\", KEYS[1], unpack(members, i, math.min(i + 999, #members)))
+end
+
+return #members
+";
+
+pub const EXPIRE:&'static str = "
+-- This script receives three parameters. The first and last are
+-- encoded with MessagePack. It sets the time to live of a model
+-- instance and the keys that share its lifecycle.
+--
+-- # model
+--
+-- Table with three attributes:
+--    id (model instance id)
+--    key (hash where the attributes are saved)
+--    name (model name)
+--
+-- # ttl
+--
+-- Seconds until the instance expires.
+--
+-- # tracked
+--
+-- Keys that share the lifecycle of this model instance, that
+-- should expire with it.
+--
+-- The expiration time is recorded in milliseconds in a sorted
+-- set, so the indices can be removed once the instance expired.
+-- Indices and uniques are kept until then.
+--
+local model   = cmsgpack.unpack(ARGV[1])
+local ttl     = tonumber(ARGV[2])
+local tracked = cmsgpack.unpack(ARGV[3])
+
+-- TIME is not deterministic, replicate the effects instead
+if redis.replicate_commands then
+	redis.replicate_commands()
+end
+
+local time = redis.call(\"TIME\")
+local expires = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000) + ttl * 1000
+
+redis.call(\"PEXPIREAT\", model.key, expires)
+
+for _, key in ipairs(tracked) do
+	redis.call(\"PEXPIREAT\", key, expires)
+end
+
+redis.call(\"ZADD\", model.name .. \":expires\", expires, model.id)
+
+return expires
+";
+
+pub const PURGE:&'static str = "
+-- This script receives the model name, and removes from the
+-- indices and uniques all instances that expired, using the
+-- keys memoized when they were saved.
+--
+-- It returns the number of instances removed.
+--
+local name = ARGV[1]
+
+-- TIME is not deterministic, replicate the effects instead
+if redis.replicate_commands then
+	redis.replicate_commands()
+end
+
+local time = redis.call(\"TIME\")
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local expired = redis.call(\"ZRANGEBYSCORE\", name .. \":expires\", \"-inf\", now)
+local purged = 0
+
+local function remove_indices(model)
+	local memo = model.key .. \":_indices\"
+
+	for _, key in ipairs(redis.call(\"SMEMBERS\", memo)) do
+		redis.call(\"SREM\", key, model.id)
+	end
+end
+
+local function remove_uniques(model)
+	local memo = model.key .. \":_uniques\"
+
+	for _, key in ipairs(redis.call(\"HKEYS\", memo)) do
+		local value = redis.call(\"HGET\", memo, key)
+
+		-- the value may have been taken by another instance
+		if redis.call(\"HGET\", key, value) == model.id then
+			redis.call(\"HDEL\", key, value)
+		end
+	end
+end
+
+local function remove_sorted(model)
+	local memo = model.key .. \":_sorted\"
+
+	for _, key in ipairs(redis.call(\"HKEYS\", memo)) do
+		redis.call(\"ZREM\", key, redis.call(\"HGET\", memo, key))
+	end
+end
+
+for _, id in ipairs(expired) do
+	local model = { id = id, name = name, key = name .. \":\" .. id }
+
+	-- instances whose expiration was removed are left alone
+	if redis.call(\"EXISTS\", model.key) == 0 then
+		remove_indices(model)
+		remove_uniques(model)
+		remove_sorted(model)
+
+		redis.call(\"SREM\", name .. \":all\", id)
+		redis.call(\"SREM\", name .. \":deleted\", id)
+		redis.call(\"DEL\",
+			model.key .. \":counters\",
+			model.key .. \":_indices\",
+			model.key .. \":_uniques\",
+			model.key .. \":_sorted\")
+
+		purged = purged + 1
+	end
+
+	redis.call(\"ZREM\", name .. \":expires\", id)
+end
+
+return purged
+";
+
+pub const SOFT_DELETE:&'static str = "
+-- This script receives one parameter encoded with MessagePack.
+-- It removes a model instance from the indices and uniques, and
+-- moves it to the set of deleted instances, keeping its hash and
+-- the keys that share its lifecycle so it can be restored.
+--
+-- # model
+--
+-- Table with three attributes:
+--    id (model instance id)
+--    key (hash where the attributes are saved)
+--    name (model name)
+--
+local model = cmsgpack.unpack(ARGV[1])
+
+local function remove_indices(model)
+	local memo = model.key .. \":_indices\"
+
+	for _, key in ipairs(redis.call(\"SMEMBERS\", memo)) do
+		redis.call(\"SREM\", key, model.id)
+	end
+end
+
+local function remove_uniques(model)
+	local memo = model.key .. \":_uniques\"
+
+	for _, key in ipairs(redis.call(\"HKEYS\", memo)) do
+		redis.call(\"HDEL\", key, redis.call(\"HGET\", memo, key))
+	end
+end
+
+local function remove_sorted(model)
+	local memo = model.key .. \":_sorted\"
+
+	for _, key in ipairs(redis.call(\"HKEYS\", memo)) do
+		redis.call(\"ZREM\", key, redis.call(\"HGET\", memo, key))
+	end
+end
+
+remove_indices(model)
+remove_uniques(model)
+remove_sorted(model)
+
+redis.call(\"SREM\", model.name .. \":all\", model.id)
+redis.call(\"SADD\", model.name .. \":deleted\", model.id)
+redis.call(\"DEL\",
+	model.key .. \":_indices\",
+	model.key .. \":_uniques\",
+	model.key .. \":_sorted\")
+
+return model.id
+";
+
+pub const PAGE:&'static str = "
+-- Gets a page of the ids in a set, after a cursor. Ids are ordered
+-- numerically, or by a value and then by id, so pages are stable
+-- when elements are added or removed.
+--
+-- KEYS[1] is the set with the ids.
+--
+-- ARGV[1] is the cursor, or an empty string for the first page.
+-- ARGV[2] is the page size.
+-- ARGV[3] is the optional pattern to sort by, as in SORT.
+-- ARGV[4] is ASC or DESC.
+-- ARGV[5] is ALPHA to compare the values lexicographically.
+-- ARGV[6] is the optional sorted set scoring the ids by the value.
+--
+-- With a sorted set, the page is read walking it from the cursor
+-- with ZRANGEBYSCORE, one score at a time, and keeping the ids in
+-- the set. Otherwise the set is read once, keeping only the first
+-- entries after the cursor instead of sorting all of them.
+--
+-- The cursor is the last id of a page and the value it was
+-- sorted by as stored, separated by a colon. The script returns
+-- the ids in the page, and the cursor of the next page or an
+-- empty string if there are no more elements.
+--
+local size   = tonumber(ARGV[2])
+local by     = ARGV[3]
+local desc   = ARGV[4] == \"DESC\"
+local alpha  = ARGV[5] == \"ALPHA\"
+local sorted = ARGV[6]
+
+local function parse(value)
+	if alpha then
+		return value or \"\"
+	end
+
+	return tonumber(value) or 0
+end
+
+local cursor
+
+if ARGV[1] ~= \"\" then
+	local id, value = string.match(ARGV[1], \"^(%d+):(.*)$\")
+	cursor = { tonumber(id), parse(value), value }
+end
+
+local function before(a, b)
+	if a[2] ~= b[2] then
+		if desc then
+			return a[2] > b[2]
+		end
+
+		return a[2] < b[2]
+	end
+
+	return a[1] < b[1]
+end
+
+-- up to size + 1 entries, to know if there is a next page
+local entries = {}
+
+local function keep(entry)
+	if cursor and not before(cursor, entry) then
+		return
+	end
+
+	if #entries > size and not before(entry, entries[#entries]) then
+		return
+	end
+
+	local low, high = 1, #entries + 1
+
+	while low < high do
+		local middle = math.floor((low + high) / 2)
+
+		if before(entry, entries[middle]) then
+			high = middle
+		else
+			low = middle + 1
+		end
+	end
+
+	table.insert(entries, low, entry)
+	entries[size + 2] = nil
+end
+
+local function walk()
+	local range = desc and \"ZREVRANGEBYSCORE\" or \"ZRANGEBYSCORE\"
+	local last  = desc and \"-inf\" or \"+inf\"
+	local from  = desc and \"+inf\" or \"-inf\"
+
+	if cursor then
+		from = cursor[3]
+	end
+
+	while #entries <= size do
+		local first = redis.call(range, sorted, from, last, \"WITHSCORES\", \"LIMIT\", 0, 1)
+
+		if #first == 0 then
+			break
+		end
+
+		local score = first[2]
+
+		for _, id in ipairs(redis.call(range, sorted, score, score)) do
+			if redis.call(\"SISMEMBER\", KEYS[1], id) == 1 then
+				keep({ tonumber(id), tonumber(score), score })
+			end
+		end
+
+		from = \"(\" .. score
+	end
+end
+
+if sorted and sorted ~= \"\" then
+	walk()
+elseif by and by ~= \"\" then
+	local values = redis.call(\"SORT\", KEYS[1], \"BY\", \"nosort\", \"GET\", \"#\", \"GET\", by)
+
+	for i = 1, #values, 2 do
+		local value = values[i + 1]
+
+		if type(value) ~= \"string\" then
+			value = \"\"
+		end
+
+		keep({ tonumber(values[i]), parse(value), value })
+	end
+else
+	for _, id in ipairs(redis.call(\"SMEMBERS\", KEYS[1])) do
+		keep({ tonumber(id), 0, \"\" })
+	end
+end
+
+local ids = {}
+
+for i = 1, math.min(size, #entries) do
+	ids[i] = entries[i][1]
+end
+
+local next = \"\"
+
+if #entries > size and size > 0 then
+	next = entries[size][1] .. \":\" .. entries[size][3]
+end
+
+return { ids, next }
+";
+
+pub const MULTI_SORT:&'static str = "
+-- Sorts the ids in a set by several keys, as SORT does with a
+-- single BY pattern: missing values are 0, and values that are not
+-- numbers fail unless compared with ALPHA. Elements with the same
+-- values are ordered by id.
+--
+-- KEYS[1] is the set with the ids.
+--
+-- ARGV[1] and ARGV[2] are the offset and count of the elements to
+-- return. A negative count returns all of them.
+-- ARGV[3] is the number of keys to sort by, followed by three
+-- arguments for each key: its pattern, as in SORT, ASC or DESC,
+-- and ALPHA to compare the values lexicographically.
+-- The remaining arguments are GET patterns, as in SORT. Without
+-- them, the ids are returned.
+--
+local offset = tonumber(ARGV[1])
+local count  = tonumber(ARGV[2])
+local nkeys  = tonumber(ARGV[3])
+
+local keys = {}
+local args = { \"SORT\", KEYS[1], \"BY\", \"nosort\", \"GET\", \"#\" }
+
+for k = 1, nkeys do
+	local base = 3 + (k - 1) * 3
+
+	keys[k] = { desc = ARGV[base + 2] == \"DESC\", alpha = ARGV[base + 3] == \"ALPHA\" }
+	args[#args + 1] = \"GET\"
+	args[#args + 1] = ARGV[base + 1]
+end
+
+local values = redis.call(unpack(args))
+local entries = {}
+
+for i = 1, #values, nkeys + 1 do
+	local entry = { id = tonumber(values[i]) }
+
+	for k = 1, nkeys do
+		if keys[k].alpha then
+			entry[k] = values[i + k] or \"\"
+		elseif values[i + k] then
+			entry[k] = tonumber(values[i + k])
+
+			if not entry[k] then
+				return redis.error_reply(\"One or more scores can't be converted into double\")
+			end
+		else
+			entry[k] = 0
+		end
+	end
+
+	entries[#entries + 1] = entry
+end
+
+table.sort(entries, function(a, b)
+	for k = 1, nkeys do
+		if a[k] ~= b[k] then
+			if keys[k].desc then
+				return a[k] > b[k]
+			end
+
+			return a[k] < b[k]
+		end
+	end
+
+	return a.id < b.id
+end)
+
+local gets = {}
+
+for i = 4 + nkeys * 3, #ARGV do
+	gets[#gets + 1] = ARGV[i]
+end
+
+local function lookup(pattern, id)
+	if pattern == \"#\" then
+		return id
+	end
+
+	local key, field = string.match(pattern, \"^(.-)%->(.+)$\")
+
+	if key then
+		return redis.call(\"HGET\", (string.gsub(key, \"%*\", id, 1)), field)
+	end
+
+	return redis.call(\"GET\", (string.gsub(pattern, \"%*\", id, 1)))
+end
+
+local last = #entries
+
+if count >= 0 then
+	last = math.min(offset + count, #entries)
+end
+
+local result = {}
+
+for i = offset + 1, last do
+	local id = entries[i].id
+
+	if #gets == 0 then
+		result[#result + 1] = id
+	else
+		for _, pattern in ipairs(gets) do
+			result[#result + 1] = lookup(pattern, id)
+		end
+	end
+end
+
+return result
+";
+
+// Used by queries to filter by the attributes of referenced objects.
+pub const VIA:&'static str = "
+-- Stores in a set the objects referencing any of the ids in another
+-- set, using the index of the reference.
+--
+-- KEYS[1] is the destination set and KEYS[2] the set with the
+-- referenced ids. ARGV[1] is the index key without the id, like
+-- `Event:indices:venue_id:`.
+--
+local ids = redis.call(\"SMEMBERS\", KEYS[2])
+
+redis.call(\"DEL\", KEYS[1])
+
+-- members are added in chunks, to stay within the limit of
+-- arguments of unpack
+local function add(members)
+	for i = 1, #members, 1000 do
+		redis.call(\"SADD\", KEYS[1], unpack(members, i, math.min(i + 999, #members)))
+	end
+end
+
+for _, id in ipairs(ids) do
+	add(redis.call(\"SMEMBERS\", ARGV[1] .. id))
+end
+
+return redis.call(\"SCARD\", KEYS[1])
+";
+
+// Used by queries to delete all the objects in a set.
+pub const DELETE_ALL:&'static str = "
+-- Deletes every model instance in a set, removing them from the
+-- indices, uniques and sorted sets using their memo keys, as the
+-- DELETE and SOFT_DELETE scripts do.
+--
+-- KEYS[1] is the set with the ids, ARGV[1] the model name and
+-- ARGV[2] is `soft` to keep the hashes and the keys that share
+-- their lifecycle so they can be restored. The remaining
+-- arguments are the patterns of those keys, with `*` in place of
+-- the id.
+--
+-- Ids without a hash are removed from the indices and from the
+-- `all` set, but are not counted. The script returns the number of
+-- deleted instances.
+--
+local name    = ARGV[1]
+local soft    = ARGV[2] == \"soft\"
+local ids     = redis.call(\"SMEMBERS\", KEYS[1])
+local deleted = 0
+
+local function remove_memos(key, id)
+	for _, index in ipairs(redis.call(\"SMEMBERS\", key .. \":_indices\")) do
+		redis.call(\"SREM\", index, id)
+	end
+
+	local memo = key .. \":_uniques\"
+
+	for _, unique in ipairs(redis.call(\"HKEYS\", memo)) do
+		redis.call(\"HDEL\", unique, redis.call(\"HGET\", memo, unique))
+	end
+
+	memo = key .. \":_sorted\"
+
+	for _, sorted in ipairs(redis.call(\"HKEYS\", memo)) do
+		redis.call(\"ZREM\", sorted, redis.call(\"HGET\", memo, sorted))
+	end
+
+	redis.call(\"DEL\", key .. \":_indices\", key .. \":_uniques\", key .. \":_sorted\")
+end
+
+for _, id in ipairs(ids) do
+	local key    = name .. \":\" .. id
+	local exists = redis.call(\"EXISTS\", key) == 1
+
+	if exists then
+		deleted = deleted + 1
+	end
+
+	remove_memos(key, id)
+	redis.call(\"SREM\", name .. \":all\", id)
+
+	if soft then
+		if exists then
+			redis.call(\"SADD\", name .. \":deleted\", id)
+		end
+	else
+		redis.call(\"SREM\", name .. \":deleted\", id)
+		redis.call(\"ZREM\", name .. \":expires\", id)
+		redis.call(\"DEL\", key, key .. \":counters\")
+
+		for i = 3, #ARGV do
+			redis.call(\"DEL\", (string.gsub(ARGV[i], \"%*\", id)))
+		end
+	end
+end
+
+return deleted
+";
+
+// Used by queries to update a field of all the objects in a set.
+pub const UPDATE_ALL:&'static str = "
+-- Sets the value of a field in every model instance in a set,
+-- updating its indices, unique and prefix index using the memo
+-- keys.
+--
+-- KEYS[1] is the set with the ids, and ARGV[1] a table encoded
+-- with MessagePack with the attributes:
+--    name (model name)
+--    field (attribute to update)
+--    indexed (present if the field is indexed)
+--    unique (value to index as unique, if the field is unique)
+--    prefix (present if the field has a prefix index)
+--    updated_at (attribute set to the server time, optional)
+--    sorted (present if updated_at is kept in a sorted set)
+--
+-- ARGV[2] is the new value, and ARGV[3] the values to index,
+-- encoded with MessagePack.
+--
+-- If the field is unique, and the value is used by another
+-- instance, or the set has more than one instance, an error is
+-- returned with the UniqueIndexViolation message.
+--
+-- The script returns the number of updated instances. Ids without
+-- an instance are skipped.
+--
+local update  = cmsgpack.unpack(ARGV[1])
+local value   = ARGV[2]
+local indices = cmsgpack.unpack(ARGV[3])
+local ids     = redis.call(\"SMEMBERS\", KEYS[1])
+
+if update.unique then
+	local key = update.name .. \":uniques:\" .. update.field
+	local id  = redis.call(\"HGET\", key, update.unique)
+
+	if #ids > 1 or (id and #ids == 1 and id ~= ids[1]) then
+		error(\"UniqueIndexViolation: \" .. update.field)
+	end
+end
+
+local now
+
+if update.updated_at then
+	-- TIME is not deterministic, replicate the effects instead
+	if redis.replicate_commands then
+		redis.replicate_commands()
+	end
+
+	now = redis.call(\"TIME\")[1]
+end
+
+local function index(key, id)
+	local memo   = key .. \":_indices\"
+	local prefix = update.name .. \":indices:\" .. update.field .. \":\"
+
+	for _, index in ipairs(redis.call(\"SMEMBERS\", memo)) do
+		if string.sub(index, 1, #prefix) == prefix then
+			redis.call(\"SREM\", index, id)
+			redis.call(\"SREM\", memo, index)
+		end
+	end
+
+	for _, indexed in ipairs(indices) do
+		redis.call(\"SADD\", prefix .. indexed, id)
+		redis.call(\"SADD\", memo, prefix .. indexed)
+	end
+end
+
+local function unique(key, id)
+	local memo   = key .. \":_uniques\"
+	local unique = update.name .. \":uniques:\" .. update.field
+	local previous = redis.call(\"HGET\", memo, unique)
+
+	if previous then
+		redis.call(\"HDEL\", unique, previous)
+	end
+
+	redis.call(\"HSET\", unique, update.unique, id)
+	redis.call(\"HSET\", memo, unique, update.unique)
+end
+
+local function prefix(key, id)
+	local memo   = key .. \":_sorted\"
+	local sorted = update.name .. \":prefix:\" .. update.field
+	local member = redis.call(\"HGET\", memo, sorted)
+
+	if member then
+		redis.call(\"ZREM\", sorted, member)
+	end
+
+	member = value .. \":\" .. id
+
+	redis.call(\"HSET\", memo, sorted, member)
+	redis.call(\"ZADD\", sorted, 0, member)
+end
+
+local function stamp(key, id)
+	redis.call(\"HSET\", key, update.updated_at, now)
+
+	if update.sorted then
+		local sorted = update.name .. \":sorted:\" .. update.updated_at
+
+		redis.call(\"HSET\", key .. \":_sorted\", sorted, id)
+		redis.call(\"ZADD\", sorted, now, id)
+	end
+end
+
+local updated = 0
+
+for _, id in ipairs(ids) do
+	local key = update.name .. \":\" .. id
+
+	if redis.call(\"EXISTS\", key) == 1 then
+		redis.call(\"HSET\", key, update.field, value)
+
+		if update.indexed then
+			index(key, id)
+		end
+
+		if update.unique then
+			unique(key, id)
+		end
+
+		if update.prefix then
+			prefix(key, id)
+		end
+
+		if now then
+			stamp(key, id)
+		end
+
+		updated = updated + 1
+	end
+end
+
+return updated
+";
+
+// Used by migrations to change the stored objects in batches.
+pub const MIGRATE:&'static str = "
+-- Applies a migration operation to a batch of model instances.
+--
+-- ARGV[1] is the operation, ARGV[2] the model name, and ARGV[3]
+-- and ARGV[4] the arguments of the operation. The remaining
+-- arguments are the ids of the instances, or the keys to rename
+-- for `rename_class`.
+--
+--    rename_attribute (from, to)
+--    add_index (field)
+--    remove_index (field)
+--    check_unique (field, key of the values seen)
+--    add_unique (field)
+--    remove_unique (field)
+--    backfill (field, value)
+--    rename_class (new name)
+--
+-- `rename_class` renames no key of the batch if the new name of one
+-- of them is in use, and returns that name instead.
+--
+-- If a unique value is already used by another instance, an error
+-- is returned with the UniqueIndexViolation message. `check_unique`
+-- only looks for those errors, recording the values of each batch
+-- in a hash to find them in the following ones, so `add_unique`
+-- can run after every batch was checked.
+--
+-- The script returns the number of processed ids or keys, or nil
+-- for `rename_class`.
+--
+local operation = ARGV[1]
+local name      = ARGV[2]
+local first     = ARGV[3]
+local second    = ARGV[4]
+
+local function starts_with(s, prefix)
+	return string.sub(s, 1, #prefix) == prefix
+end
+
+local function rename_attribute(key, id, from, to)
+	local value = redis.call(\"HGET\", key, from)
+
+	if value then
+		redis.call(\"HSET\", key, to, value)
+		redis.call(\"HDEL\", key, from)
+	end
+
+	local memo   = key .. \":_indices\"
+	local prefix = name .. \":indices:\" .. from .. \":\"
+
+	for _, index in ipairs(redis.call(\"SMEMBERS\", memo)) do
+		if starts_with(index, prefix) then
+			local renamed = name .. \":indices:\" .. to .. \":\" .. string.sub(index, #prefix + 1)
+
+			redis.call(\"SREM\", index, id)
+			redis.call(\"SADD\", renamed, id)
+			redis.call(\"SREM\", memo, index)
+			redis.call(\"SADD\", memo, renamed)
+		end
+	end
+
+	memo = key .. \":_uniques\"
+
+	local unique = name .. \":uniques:\" .. from
+	local unique_value = redis.call(\"HGET\", memo, unique)
+
+	if unique_value then
+		redis.call(\"HDEL\", unique, unique_value)
+		redis.call(\"HSET\", name .. \":uniques:\" .. to, unique_value, id)
+		redis.call(\"HDEL\", memo, unique)
+		redis.call(\"HSET\", memo, name .. \":uniques:\" .. to, unique_value)
+	end
+
+	memo = key .. \":_sorted\"
+
+	for _, kind in ipairs({ \":sorted:\", \":prefix:\" }) do
+		local sorted = name .. kind .. from
+		local member = redis.call(\"HGET\", memo, sorted)
+
+		if member then
+			local score = redis.call(\"ZSCORE\", sorted, member)
+
+			redis.call(\"ZREM\", sorted, member)
+
+			if score then
+				redis.call(\"ZADD\", name .. kind .. to, score, member)
+			end
+
+			redis.call(\"HDEL\", memo, sorted)
+			redis.call(\"HSET\", memo, name .. kind .. to, member)
+		end
+	end
+end
+
+local function add_index(key, id, field)
+	local value = redis.call(\"HGET\", key, field)
+
+	if value then
+		local index = name .. \":indices:\" .. field .. \":\" .. value
+
+		redis.call(\"SADD\", index, id)
+		redis.call(\"SADD\", key .. \":_indices\", index)
+	end
+end
+
+local function remove_index(key, id, field)
+	local memo   = key .. \":_indices\"
+	local prefix = name .. \":indices:\" .. field .. \":\"
+
+	for _, index in ipairs(redis.call(\"SMEMBERS\", memo)) do
+		if starts_with(index, prefix) then
+			redis.call(\"SREM\", index, id)
+			redis.call(\"SREM\", memo, index)
+		end
+	end
+end
+
+local function add_unique(key, id, field)
+	local value = redis.call(\"HGET\", key, field)
+
+	if value then
+		local unique = name .. \":uniques:\" .. field
+		local holder = redis.call(\"HGET\", unique, value)
+
+		if holder and holder ~= id then
+			error(\"UniqueIndexViolation: \" .. field)
+		end
+
+		redis.call(\"HSET\", unique, value, id)
+		redis.call(\"HSET\", key .. \":_uniques\", unique, value)
+	end
+end
+
+local function check_unique(key, id, field, seen)
+	local value = redis.call(\"HGET\", key, field)
+
+	if value then
+		local holder = redis.call(\"HGET\", name .. \":uniques:\" .. field, value)
+		local first  = redis.call(\"HGET\", seen, value)
+
+		if (holder and holder ~= id) or (first and first ~= id) then
+			error(\"UniqueIndexViolation: \" .. field)
+		end
+
+		redis.call(\"HSET\", seen, value, id)
+	end
+end
+
+local function remove_unique(key, field)
+	local memo   = key .. \":_uniques\"
+	local unique = name .. \":uniques:\" .. field
+	local value  = redis.call(\"HGET\", memo, unique)
+
+	if value then
+		redis.call(\"HDEL\", unique, value)
+		redis.call(\"HDEL\", memo, unique)
+	end
+end
+
+local function renamed_key(key, to)
+	return to .. \":\" .. string.sub(key, #name + 2)
+end
+
+-- Keys with the old name are renamed, including the names stored
+-- in the memo keys.
+local function rename_key(key, to)
+	if redis.call(\"EXISTS\", key) == 0 or not starts_with(key, name .. \":\") then
+		return
+	end
+
+	local prefix  = name .. \":\"
+	local renamed = renamed_key(key, to)
+
+	if string.sub(key, -9) == \":_indices\" then
+		for _, index in ipairs(redis.call(\"SMEMBERS\", key)) do
+			if starts_with(index, prefix) then
+				redis.call(\"SREM\", key, index)
+				redis.call(\"SADD\", key, to .. \":\" .. string.sub(index, #prefix + 1))
+			end
+		end
+	elseif string.sub(key, -9) == \":_uniques\" or string.sub(key, -8) == \":_sorted\" then
+		for _, field in ipairs(redis.call(\"HKEYS\", key)) do
+			if starts_with(field, prefix) then
+				local value = redis.call(\"HGET\", key, field)
+
+				redis.call(\"HDEL\", key, field)
+				redis.call(\"HSET\", key, to .. \":\" .. string.sub(field, #prefix + 1), value)
+			end
+		end
+	end
+
+	redis.call(\"RENAMENX\", key, renamed)
+end
+
+if operation == \"rename_class\" then
+	for i = 5, #ARGV do
+		local key = ARGV[i]
+
+		if starts_with(key, name .. \":\") and
+				redis.call(\"EXISTS\", renamed_key(key, first)) == 1 then
+			return renamed_key(key, first)
+		end
+	end
+
+	for i = 5, #ARGV do
+		rename_key(ARGV[i], first)
+	end
+
+	return false
+end
+
+for i = 5, #ARGV do
+	local id  = ARGV[i]
+	local key = name .. \":\" .. id
+
+	if redis.call(\"EXISTS\", key) == 1 then
+		if operation == \"rename_attribute\" then
+			rename_attribute(key, id, first, second)
+		elseif operation == \"add_index\" then
+			add_index(key, id, first)
+		elseif operation == \"remove_index\" then
+			remove_index(key, id, first)
+		elseif operation == \"check_unique\" then
+			check_unique(key, id, first, second)
+		elseif operation == \"add_unique\" then
+			add_unique(key, id, first)
+		elseif operation == \"remove_unique\" then
+			remove_unique(key, first)
+		elseif operation == \"backfill\" then
+			redis.call(\"HSETNX\", key, first, second)
+		end
+	end
+end
+
+return #ARGV - 4
+";
+
+// Used by migrations to release their lock.
+pub const UNLOCK:&'static str = "
+-- Deletes the lock KEYS[1] if it still has the value ARGV[1], so a
+-- lock that expired and was taken by another process is kept.
+--
+-- The script returns 1 if the lock was deleted, and 0 if not.
+--
+if redis.call(\"GET\", KEYS[1]) == ARGV[1] then
+	return redis.call(\"DEL\", KEYS[1])
+end
+
+return 0
+";
+
+// Used by check to repair inconsistent entries.
+pub const REPAIR:&'static str = "
+-- Repairs the entries found by a consistency check, checking each
+-- one again first, since objects may be saved or deleted after the
+-- check read them.
+--
+-- ARGV[1] is the model name. The remaining arguments are triples
+-- of a kind of entry and its two arguments:
+--
+--    missing (id): removed from `all` and `deleted` if the hash
+--        does not exist.
+--    orphan (id): added back to `all` if the hash exists and the
+--        id is neither in `all` nor in `deleted`.
+--    index (key, id): removed from the index if the id is not in
+--        `all`.
+--    unique (key, value): removed from the unique hash if its id
+--        is not in `all`, or the object does not record the value.
+--    sorted (key, member): removed from a sorted, prefix or geo
+--        index if its id is not in `all`, or from `expires` if its
+--        id is neither in `all` nor in `deleted`.
+--    memo (memo key, entry): removed from the `_indices`,
+--        `_uniques` or `_sorted` key if the index or unique does
+--        not have the object.
+--    key (key, id): deleted if the id is neither in `all` nor in
+--        `deleted`.
+--
+-- The script returns the number of entries changed.
+--
+local name    = ARGV[1]
+local changed = 0
+
+local function starts_with(s, prefix)
+	return string.sub(s, 1, #prefix) == prefix
+end
+
+local function indexed(id)
+	return redis.call(\"SISMEMBER\", name .. \":all\", id) == 1
+end
+
+local function member(id)
+	return indexed(id) or redis.call(\"SISMEMBER\", name .. \":deleted\", id) == 1
+end
+
+local function remove(command, key, value)
+	changed = changed + redis.call(command, key, value)
+end
+
+local function memo(key, entry)
+	local id = string.match(key, \":(%d+):_%a+$\")
+
+	if string.sub(key, -9) == \":_indices\" then
+		if redis.call(\"SISMEMBER\", entry, id) == 0 then
+			remove(\"SREM\", key, entry)
+		end
+	elseif string.sub(key, -8) == \":_sorted\" then
+		local sorted = redis.call(\"HGET\", key, entry)
+
+		if sorted and not redis.call(\"ZSCORE\", entry, sorted) then
+			remove(\"HDEL\", key, entry)
+		end
+	else
+		local value = redis.call(\"HGET\", key, entry)
+
+		if value and redis.call(\"HGET\", entry, value) ~= id then
+			remove(\"HDEL\", key, entry)
+		end
+	end
+end
+
+for i = 2, #ARGV, 3 do
+	local kind, first, second = ARGV[i], ARGV[i + 1], ARGV[i + 2]
+
+	if kind == \"missing\" then
+		if redis.call(\"EXISTS\", name .. \":\" .. first) == 0 then
+			remove(\"SREM\", name .. \":all\", first)
+			remove(\"SREM\", name .. \":deleted\", first)
+		end
+	elseif kind == \"orphan\" then
+		if not member(first) and redis.call(\"EXISTS\", name .. \":\" .. first) == 1 then
+			changed = changed + redis.call(\"SADD\", name .. \":all\", first)
+		end
+	elseif kind == \"index\" then
+		if not indexed(second) then
+			remove(\"SREM\", first, second)
+		end
+	elseif kind == \"sorted\" then
+		local id = second
+
+		if starts_with(first, name .. \":prefix:\") then
+			id = string.match(second, \":(%d+)$\") or \"\"
+		end
+
+		if first == name .. \":expires\" and not member(id) or
+				first ~= name .. \":expires\" and not indexed(id) then
+			remove(\"ZREM\", first, second)
+		end
+	elseif kind == \"unique\" then
+		local id = redis.call(\"HGET\", first, second)
+
+		if id and (not indexed(id) or
+				redis.call(\"HGET\", name .. \":\" .. id .. \":_uniques\", first) ~= second) then
+			remove(\"HDEL\", first, second)
+		end
+	elseif kind == \"memo\" then
+		memo(first, second)
+	elseif kind == \"key\" then
+		if not member(second) then
+			changed = changed + redis.call(\"DEL\", first)
+		end
+	end
+end
+
+return changed
+";
@@ -0,0 +1,335 @@
+//! Versioned changes to the stored objects, for when a model definition
+//! changes after instances were saved.
+//!
+//! Each migration has a version number, and the versions already applied
+//! are recorded in the `ohmers:migrations` set, so running the migrations
+//! again only applies the new ones. Operations on a model run in batches
+//! over the ids in `Class:all` and `Class:deleted`.
+
+use std::collections::BTreeMap;
+use std::process;
+use std::thread::sleep;
+use std::time::Duration;
+
+use redis::{self, Commands};
+
+use lua::{MIGRATE, UNLOCK};
+use trace::traced;
+use {lua_script, save_error, temporary_key, Connection, OhmerError};
+
+/// Key of the set with the applied versions.
+pub const MIGRATIONS_KEY: &'static str = "ohmers:migrations";
+
+/// Key of the lock taken while running the migrations.
+pub const LOCK_KEY: &'static str = "ohmers:migrations:lock";
+
+/// An operation of a migration.
+#[derive(Clone, Debug, PartialEq)]
+enum Operation {
+    RenameAttribute(String, String, String),
+    AddIndex(String, String),
+    RemoveIndex(String, String),
+    AddUnique(String, String),
+    RemoveUnique(String, String),
+    RenameClass(String, String),
+    Backfill(String, String, String),
+}
+
+/// Operations applied together under a version number.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Migration {
+    operations: Vec<Operation>,
+}
+
+impl Migration {
+    /// Renames an attribute of every instance of `class`, along with its
+    /// index, unique, sorted and prefix keys.
+    pub fn rename_attribute(&mut self, class: &str, from: &str, to: &str) -> &mut Self {
+        self.operations.push(Operation::RenameAttribute(class.to_string(), from.to_string(), to.to_string()));
+        self
+    }
+
+    /// Indexes the value of `field` of every instance of `class`.
+    /// Values are indexed as stored, without normalization or splitting
+    /// text in terms; `reindex` rebuilds the index as `save` writes it.
+    pub fn add_index(&mut self, class: &str, field: &str) -> &mut Self {
+        self.operations.push(Operation::AddIndex(class.to_string(), field.to_string()));
+        self
+    }
+
+    /// Removes every instance of `class` from the indices of `field`.
+    pub fn remove_index(&mut self, class: &str, field: &str) -> &mut Self {
+        self.operations.push(Operation::RemoveIndex(class.to_string(), field.to_string()));
+        self
+    }
+
+    /// Adds a unique index on `field`. Every instance is checked before
+    /// writing the index, and the migration fails with
+    /// `UniqueIndexViolation` without changes if two instances have the
+    /// same value. Values are compared and indexed as stored, without
+    /// normalization; `reindex` rebuilds the index as `save` writes it.
+    pub fn add_unique(&mut self, class: &str, field: &str) -> &mut Self {
+        self.operations.push(Operation::AddUnique(class.to_string(), field.to_string()));
+        self
+    }
+
+    /// Removes the unique index on `field`.
+    pub fn remove_unique(&mut self, class: &str, field: &str) -> &mut Self {
+        self.operations.push(Operation::RemoveUnique(class.to_string(), field.to_string()));
+        self
+    }
+
+    /// Renames every key of the model `from`, including its instances,
+    /// indices, sets, lists and counters. The migration fails with
+    /// `KeyExists` if a key of the model `to` already exists; it is checked
+    /// before renaming, and again for each batch, whose keys are not
+    /// renamed if one of the new names is in use.
+    pub fn rename_class(&mut self, from: &str, to: &str) -> &mut Self {
+        self.operations.push(Operation::RenameClass(from.to_string(), to.to_string()));
+        self
+    }
+
+    /// Sets `field` to `value` in every instance of `class` without it.
+    pub fn backfill(&mut self, class: &str, field: &str, value: &str) -> &mut Self {
+        self.operations.push(Operation::Backfill(class.to_string(), field.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// An ordered list of migrations.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::*;
+/// use ohmers::migration::Migrations;
+///
+/// model!(
+///     Crate {
+///         uniques { code:String = "".to_string(); };
+///         indices { port:String = "".to_string(); };
+///         weight:u32 = 0;
+///     });
+///
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// # let _:() = redis::cmd("SREM").arg("ohmers:migrations").arg(1).arg(2).query(&client).unwrap();
+/// let mut migrations = Migrations::new();
+/// migrations.version(1).backfill("Crate", "port", "Valparaiso");
+/// migrations.version(2).add_index("Crate", "port").add_unique("Crate", "code");
+/// migrations.run(&client).unwrap();
+/// assert_eq!(migrations.run(&client).unwrap(), vec![]);
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Migrations {
+    versions: BTreeMap<u32, Migration>,
+    batch: usize,
+    lock_ttl: usize,
+}
+
+impl Migrations {
+    /// Creates an empty list, processing 1000 ids per batch, with a lock
+    /// that expires in 600 seconds.
+    pub fn new() -> Self {
+        Migrations {
+            versions: BTreeMap::new(),
+            batch: 1000,
+            lock_ttl: 600,
+        }
+    }
+
+    /// Changes the number of ids or keys processed in each request.
+    pub fn batch(&mut self, batch: usize) -> &mut Self {
+        self.batch = batch;
+        self
+    }
+
+    /// Changes the seconds after which the lock taken by `run` expires, in
+    /// case the process stops without releasing it. It should be longer
+    /// than the migrations take.
+    pub fn lock_ttl(&mut self, lock_ttl: usize) -> &mut Self {
+        self.lock_ttl = lock_ttl;
+        self
+    }
+
+    /// Migration with number `version`, created if it does not exist.
+    pub fn version(&mut self, version: u32) -> &mut Migration {
+        self.versions.entry(version).or_insert_with(Migration::default)
+    }
+
+    /// Versions not applied in the server yet, in ascending order.
+    pub fn pending<'a>(&self, r: impl Connection<'a>) -> Result<Vec<u32>, OhmerError> {
+        let r = r.connection();
+        let applied:Vec<u32> = try!(traced(r).smembers(MIGRATIONS_KEY));
+        Ok(self.versions.keys().cloned().filter(|version| !applied.contains(version)).collect())
+    }
+
+    /// Applies the pending migrations in order, and returns their versions.
+    /// If an operation fails, its version and the following ones are not
+    /// recorded, and the error is returned.
+    ///
+    /// The migrations are run holding a lock in `LOCK_KEY`, so a process
+    /// waits for another one running them to finish, and then applies only
+    /// the versions still pending.
+    pub fn run<'a>(&self, r: impl Connection<'a>) -> Result<Vec<u32>, OhmerError> {
+        let r = r.connection();
+        let token = try!(self.lock(r));
+        let result = self.run_pending(r);
+        let _:usize = try!(lua_script(UNLOCK).key(LOCK_KEY).arg(token).invoke(&traced(r)));
+        result
+    }
+
+    /// Waits until the lock is taken, and returns its value.
+    fn lock(&self, r: &redis::ConnectionLike) -> Result<String, OhmerError> {
+        let token = format!("{}:{}", process::id(), temporary_key());
+        loop {
+            let locked:Option<String> = try!(redis::cmd("SET")
+                    .arg(LOCK_KEY)
+                    .arg(&*token)
+                    .arg("NX")
+                    .arg("EX")
+                    .arg(self.lock_ttl)
+                    .query(&traced(r)));
+            if locked.is_some() {
+                return Ok(token);
+            }
+            sleep(Duration::from_millis(100));
+        }
+    }
+
+    fn run_pending(&self, r: &redis::ConnectionLike) -> Result<Vec<u32>, OhmerError> {
+        let pending = try!(self.pending(r));
+        for version in pending.iter() {
+            for operation in self.versions[version].operations.iter() {
+                try!(self.apply(operation, r));
+            }
+            let _:() = try!(traced(r).sadd(MIGRATIONS_KEY, *version));
+        }
+        Ok(pending)
+    }
+
+    fn apply(&self, operation: &Operation, r: &redis::ConnectionLike) -> Result<(), OhmerError> {
+        let (op, class, first, second) = match *operation {
+            Operation::RenameAttribute(ref class, ref from, ref to) => ("rename_attribute", class, &**from, &**to),
+            Operation::AddIndex(ref class, ref field) => ("add_index", class, &**field, ""),
+            Operation::RemoveIndex(ref class, ref field) => ("remove_index", class, &**field, ""),
+            Operation::AddUnique(ref class, ref field) => ("add_unique", class, &**field, ""),
+            Operation::RemoveUnique(ref class, ref field) => ("remove_unique", class, &**field, ""),
+            Operation::Backfill(ref class, ref field, ref value) => ("backfill", class, &**field, &**value),
+            Operation::RenameClass(ref from, ref to) => return self.rename_class(from, to, r),
+        };
+        if op == "add_unique" {
+            // a duplicate found while writing would leave the index half built
+            let seen = temporary_key();
+            let checked = self.batches(class, r, |ids| migrate("check_unique", class, first, &*seen, ids, r));
+            let _:() = try!(traced(r).del(&*seen));
+            try!(checked);
+        }
+        try!(self.batches(class, r, |ids| migrate(op, class, first, second, ids, r)));
+        if op == "remove_unique" {
+            let _:() = try!(traced(r).del(format!("{}:uniques:{}", class, first)));
+        }
+        Ok(())
+    }
+
+    /// Calls `f` with each batch of ids in `Class:all` and `Class:deleted`.
+    fn batches<F>(&self, class: &str, r: &redis::ConnectionLike, mut f: F) -> Result<(), OhmerError>
+            where F: FnMut(&[String]) -> Result<(), OhmerError> {
+        for set in ["all", "deleted"].iter() {
+            let key = format!("{}:{}", class, set);
+            let mut cursor = 0;
+            loop {
+                let (next, ids):(u64, Vec<String>) = try!(redis::cmd("SSCAN")
+                        .arg(&*key)
+                        .arg(cursor)
+                        .arg("COUNT")
+                        .arg(self.batch)
+                        .query(&traced(r)));
+                if ids.len() > 0 {
+                    try!(f(&ids));
+                }
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renames the keys of a model. Scans are repeated until no key is
+    /// left, since renaming keys during a scan may skip some of them.
+    fn rename_class(&self, from: &str, to: &str, r: &redis::ConnectionLike) -> Result<(), OhmerError> {
+        // renaming only some batches would mix the objects of both models
+        let mut cursor = 0;
+        loop {
+            let (next, keys):(u64, Vec<String>) = try!(redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(format!("{}:*", to))
+                    .arg("COUNT")
+                    .arg(self.batch)
+                    .query(&traced(r)));
+            if let Some(key) = keys.into_iter().next() {
+                return Err(OhmerError::KeyExists(key));
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        loop {
+            let mut renamed = 0;
+            let mut cursor = 0;
+            loop {
+                let (next, keys):(u64, Vec<String>) = try!(redis::cmd("SCAN")
+                        .arg(cursor)
+                        .arg("MATCH")
+                        .arg(format!("{}:*", from))
+                        .arg("COUNT")
+                        .arg(self.batch)
+                        .query(&traced(r)));
+                if keys.len() > 0 {
+                    renamed += keys.len();
+                    let used:Option<String> = try!(lua_script(MIGRATE)
+                            .arg("rename_class")
+                            .arg(from)
+                            .arg(to)
+                            .arg("")
+                            .arg(&*keys)
+                            .invoke(&traced(r)));
+                    if let Some(key) = used {
+                        return Err(OhmerError::KeyExists(key));
+                    }
+                }
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+            if renamed == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Runs an operation of the `MIGRATE` script over a batch.
+fn migrate(op: &str, class: &str, first: &str, second: &str, batch: &[String], r: &redis::ConnectionLike) -> Result<(), OhmerError> {
+    let script = lua_script(MIGRATE);
+    let result:Result<usize, _> = script
+            .arg(op)
+            .arg(class)
+            .arg(first)
+            .arg(second)
+            .arg(batch)
+            .invoke(&traced(r));
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(save_error(&*format!("{}", e)).unwrap_or(OhmerError::RedisError(e))),
+    }
+}
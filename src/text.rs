@@ -0,0 +1,103 @@
+//! Processing of text values before they are indexed: splitting of text
+//! fields in terms for full-text search, and normalization of index values.
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Common English words, not indexed when a model declares `stop_words;`.
+pub static STOP_WORDS: &'static [&'static str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in",
+    "into", "is", "it", "no", "not", "of", "on", "or", "such", "that", "the",
+    "their", "then", "there", "these", "they", "this", "to", "was", "will",
+    "with",
+];
+
+/// Splits `text` in lowercase words, skipping any word in `stop_words`.
+/// Each word is reduced to its stem if `stemming` is true.
+/// Every term is returned only once.
+///
+/// # Examples
+///
+/// ```rust
+/// use ohmers::text::{tokenize, STOP_WORDS};
+///
+/// assert_eq!(tokenize("The Birthday Parties!", STOP_WORDS, true), vec!["birthday", "party"]);
+/// assert_eq!(tokenize("The Birthday Parties!", &[], false), vec!["the", "birthday", "parties"]);
+/// ```
+pub fn tokenize(text: &str, stop_words: &[&str], stemming: bool) -> Vec<String> {
+    let mut terms: Vec<String> = vec![];
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if word.len() == 0 {
+            continue;
+        }
+        let word = word.to_lowercase();
+        if stop_words.contains(&&*word) {
+            continue;
+        }
+        let term = if stemming { stem(&*word) } else { word };
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+/// Removes the plural suffix of a lowercase English word, using Harman's
+/// "S" stemmer.
+///
+/// # Examples
+///
+/// ```rust
+/// use ohmers::text::stem;
+///
+/// assert_eq!(stem("parties"), "party");
+/// assert_eq!(stem("houses"), "house");
+/// assert_eq!(stem("cats"), "cat");
+/// assert_eq!(stem("glass"), "glass");
+/// ```
+pub fn stem(word: &str) -> String {
+    if word.len() <= 3 {
+        word.to_string()
+    } else if word.ends_with("ies") && !word.ends_with("eies") && !word.ends_with("aies") {
+        format!("{}y", &word[..word.len() - 3])
+    } else if word.ends_with("es") && !word.ends_with("aes") && !word.ends_with("ees") && !word.ends_with("oes") {
+        word[..word.len() - 1].to_string()
+    } else if word.ends_with("s") && !word.ends_with("us") && !word.ends_with("ss") {
+        word[..word.len() - 1].to_string()
+    } else {
+        word.to_string()
+    }
+}
+
+/// A transformation applied to the value of a field before it is used in a
+/// unique or index key, so equivalent values share the same key.
+///
+/// # Examples
+///
+/// ```rust
+/// use ohmers::text::Normalizer;
+///
+/// assert_eq!(Normalizer::Lowercase.normalize("Alice@X.com"), "alice@x.com");
+/// assert_eq!(Normalizer::Trim.normalize(" alice "), "alice");
+/// assert_eq!(Normalizer::Nfkc.normalize("\u{FB01}le"), "file");
+/// fn digits(value: &str) -> String { value.chars().filter(|c| c.is_digit(10)).collect() }
+/// assert_eq!(Normalizer::Custom(digits).normalize("555-1234"), "5551234");
+/// ```
+#[derive(Clone, Copy)]
+pub enum Normalizer {
+    Lowercase,
+    Trim,
+    Nfkc,
+    Custom(fn(&str) -> String),
+}
+
+impl Normalizer {
+    /// Applies the transformation to `value`.
+    pub fn normalize(&self, value: &str) -> String {
+        match *self {
+            Normalizer::Lowercase => value.to_lowercase(),
+            Normalizer::Trim => value.trim().to_string(),
+            Normalizer::Nfkc => value.nfkc().collect(),
+            Normalizer::Custom(f) => f(value),
+        }
+    }
+}
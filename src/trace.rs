@@ -0,0 +1,170 @@
+//! Inspection of the commands sent to Redis, to debug queries and measure
+//! how long each request takes.
+
+use std::time::{Duration, Instant};
+
+use redis::{self, ConnectionLike, RedisResult, Value};
+
+use lua::{DELETE, DELETE_ALL, EXPIRE, MIGRATE, MULTI_SORT, PAGE, PURGE, RANGE_STORE, REPAIR, SAVE, SOFT_DELETE, UNLOCK, UPDATE_ALL, VIA};
+use {lua_script, Connection};
+
+/// A request sent to Redis.
+#[derive(Debug)]
+pub struct Trace {
+    /// Commands in the request, more than one if it was a pipeline.
+    pub commands: Vec<String>,
+    /// Time until the response was read.
+    pub duration: Duration,
+}
+
+/// Closure called after each request sent through a `Traced` connection.
+pub type Tracer<'a> = Box<Fn(&Trace) + 'a>;
+
+/// Scripts replaced by their name when formatting commands.
+const SCRIPTS: [(&'static str, &'static str); 14] = [
+    ("SAVE", SAVE), ("DELETE", DELETE), ("RANGE_STORE", RANGE_STORE),
+    ("EXPIRE", EXPIRE), ("PURGE", PURGE), ("SOFT_DELETE", SOFT_DELETE),
+    ("PAGE", PAGE), ("MULTI_SORT", MULTI_SORT), ("VIA", VIA),
+    ("DELETE_ALL", DELETE_ALL), ("UPDATE_ALL", UPDATE_ALL), ("MIGRATE", MIGRATE),
+    ("REPAIR", REPAIR), ("UNLOCK", UNLOCK),
+];
+
+thread_local!(
+    /// Hashes of the scripts, sent by EVALSHA.
+    static HASHES: Vec<(&'static str, String)> = SCRIPTS.iter()
+        .map(|&(name, script)| (name, lua_script(script).get_hash().to_string()))
+        .collect()
+);
+
+/// Formats a command as its arguments separated by spaces. Arguments that
+/// are not printable are quoted, and scripts, or their hashes, are replaced
+/// by their name.
+///
+/// # Examples
+///
+/// ```rust
+/// use ohmers::trace::format_command;
+///
+/// let command = vec![b"HSET".to_vec(), b"Parcel:1".to_vec(), b"label".to_vec(), b"fragile glass".to_vec()];
+/// assert_eq!(format_command(&command), "HSET Parcel:1 label \"fragile glass\"");
+/// ```
+pub fn format_command(args: &[Vec<u8>]) -> String {
+    HASHES.with(|hashes| args.iter().map(|arg| {
+        if let Some(&(name, _)) = SCRIPTS.iter().find(|&&(_, script)| script.as_bytes() == &**arg) {
+            return format!("<{}>", name);
+        }
+        if arg.len() == 40 {
+            if let Some(&(name, _)) = hashes.iter().find(|&&(_, ref hash)| hash.as_bytes() == &**arg) {
+                return format!("<{}>", name);
+            }
+        }
+        let s = String::from_utf8_lossy(arg);
+        if s.len() == 0 || s.chars().any(|c| c.is_whitespace() || c.is_control() || c == '"') {
+            format!("{:?}", s)
+        } else {
+            s.into_owned()
+        }
+    }).collect::<Vec<_>>().join(" "))
+}
+
+/// Splits packed commands in their arguments.
+fn unpack(packed: &[u8]) -> Vec<Vec<Vec<u8>>> {
+    fn number(packed: &[u8], pos: &mut usize) -> usize {
+        let start = *pos + 1;
+        let end = start + packed[start..].iter().position(|&c| c == b'\r').unwrap_or(0);
+        *pos = end + 2;
+        String::from_utf8_lossy(&packed[start..end]).parse().unwrap_or(0)
+    }
+
+    let mut commands = vec![];
+    let mut pos = 0;
+    while pos < packed.len() {
+        let len = number(packed, &mut pos);
+        let mut args = Vec::with_capacity(len);
+        for _ in 0..len {
+            let size = number(packed, &mut pos);
+            args.push(packed[pos..pos + size].to_vec());
+            pos += size + 2;
+        }
+        commands.push(args);
+    }
+    commands
+}
+
+/// A connection that calls a tracer after every request sent through it
+/// by save, load, delete, collections and queries. It is passed instead of
+/// the client to the calls to trace.
+///
+/// # Examples
+///
+/// ```rust
+/// # #[macro_use(model, create)] extern crate ohmers;
+/// # extern crate rustc_serialize;
+/// # extern crate redis;
+/// # use ohmers::Ohmer;
+/// use ohmers::trace::{Trace, Traced};
+///
+/// model!(
+///     Parcel {
+///         weight:u32 = 0;
+///     });
+///
+/// fn log(trace: &Trace) {
+///     println!("{:?} {}", trace.duration, trace.commands.join("; "));
+/// }
+///
+/// # fn main() {
+/// # let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+/// let traced = Traced::new(&client, Box::new(log));
+/// create!(Parcel { weight: 3, }, &traced).unwrap();
+/// # }
+/// ```
+pub struct Traced<'a> {
+    r: &'a ConnectionLike,
+    tracer: Option<Tracer<'a>>,
+}
+
+/// Wraps `r` without a tracer, to send commands with `redis::Commands`
+/// through any connection. Requests are still reported if `r` is traced.
+pub fn traced(r: &ConnectionLike) -> Traced {
+    Traced { r: r, tracer: None }
+}
+
+impl<'a> Traced<'a> {
+    /// Wraps `r` to call `tracer` after each request.
+    pub fn new(r: impl Connection<'a>, tracer: Tracer<'a>) -> Self {
+        Traced { r: r.connection(), tracer: Some(tracer) }
+    }
+
+    fn report(&self, packed: &[u8], start: Instant) {
+        if let Some(ref tracer) = self.tracer {
+            let duration = start.elapsed();
+            tracer(&Trace {
+                commands: unpack(packed).iter().map(|args| format_command(args)).collect(),
+                duration: duration,
+            });
+        }
+    }
+}
+
+impl<'a> ConnectionLike for Traced<'a> {
+    fn req_packed_command(&self, cmd: &[u8]) -> RedisResult<Value> {
+        let start = Instant::now();
+        let result = self.r.req_packed_command(cmd);
+        self.report(cmd, start);
+        result
+    }
+
+    fn req_packed_commands(&self, cmd: &[u8], offset: usize, count: usize) -> RedisResult<Vec<Value>> {
+        let start = Instant::now();
+        let result = self.r.req_packed_commands(cmd, offset, count);
+        self.report(cmd, start);
+        result
+    }
+
+    fn get_db(&self) -> i64 {
+        self.r.get_db()
+    }
+}
+
+impl<'a> redis::Commands for Traced<'a> {}
@@ -0,0 +1,129 @@
+#[macro_use(model, create, insert, incr)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{deleted_query, get, restore, with, Counter, Ohmer, OhmerError, Query, Set, UpdatedAt};
+use redis::Commands;
+
+model!(
+    Extension {
+        name:String = "".to_string();
+    });
+
+model!(
+    Browser {
+        uniques { serial:String = "".to_string(); };
+        indices {
+            name:String = "".to_string();
+            updated_at:UpdatedAt = UpdatedAt::new();
+        };
+        text { notes:String = "".to_string(); };
+        prefix { version:String = "".to_string(); };
+        composite_indices { os, arch; };
+        os:String = "".to_string();
+        arch:String = "".to_string();
+        launches:Counter = Counter;
+        extensions:Set<Extension> = Set::new();
+    });
+
+model!(
+    Tab {
+        soft_delete;
+        indices { url:String = "".to_string(); };
+    });
+
+fn cleanup(client: &redis::Client) {
+    let keys:Vec<String> = client.keys("Browser:indices:*").unwrap();
+    for key in keys {
+        let _:bool = client.del(key).unwrap();
+    }
+    let _:bool = client.del("Browser:uniques:serial").unwrap();
+    let _:bool = client.del("Browser:prefix:version").unwrap();
+}
+
+#[test]
+fn test_update_all() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    cleanup(&client);
+
+    let b1 = create!(Browser { serial: "s1".to_string(), name: "Firefox".to_string(), notes: "fast".to_string(), version: "44.0".to_string(), }, &client).unwrap();
+    let b2 = create!(Browser { serial: "s2".to_string(), name: "Firefox".to_string(), version: "45.1".to_string(), }, &client).unwrap();
+    let b3 = create!(Browser { serial: "s3".to_string(), name: "Chrome".to_string(), version: "48.0".to_string(), }, &client).unwrap();
+
+    let firefox = Query::<Browser>::find("name", "Firefox", &client);
+    assert_eq!(firefox.update_all("notes", "Slow and steady").unwrap(), 2);
+    assert_eq!(Query::<Browser>::search("notes", "fast", &client).ids().unwrap(), vec![]);
+    assert_eq!(Query::<Browser>::search("notes", "steady", &client).ids().unwrap(), vec![b1.id, b2.id]);
+    let updated = get::<Browser>(b1.id, &client).unwrap();
+    assert_eq!(&*updated.notes, "Slow and steady");
+    assert!(updated.updated_at.time() >= b1.updated_at.time());
+    assert!(Query::<Browser>::range("updated_at", updated.updated_at.time() as f64, updated.updated_at.time() as f64, &client).ids().unwrap().contains(&b1.id));
+
+    assert_eq!(firefox.update_all("version", "46.0").unwrap(), 2);
+    assert_eq!(Query::<Browser>::starts_with("version", "46", &client).ids().unwrap(), vec![b1.id, b2.id]);
+    assert_eq!(Query::<Browser>::starts_with("version", "4", &client).ids().unwrap(), vec![b1.id, b2.id, b3.id]);
+    assert_eq!(Query::<Browser>::starts_with("version", "44", &client).ids().unwrap(), vec![]);
+
+    // a single object may take a unique value
+    assert_eq!(Query::<Browser>::find("name", "Chrome", &client).update_all("serial", "s9").unwrap(), 1);
+    assert_eq!(with::<Browser, _>("serial", "s9", &client).unwrap().unwrap().id, b3.id);
+    assert!(with::<Browser, _>("serial", "s3", &client).unwrap().is_none());
+    assert_eq!(firefox.update_all("serial", "s5").unwrap_err(), OhmerError::UniqueIndexViolation("serial".to_string()));
+    assert_eq!(Query::<Browser>::find("name", "Chrome", &client).update_all("serial", "s1").unwrap_err(),
+        OhmerError::UniqueIndexViolation("serial".to_string()));
+    assert_eq!(&*get::<Browser>(b3.id, &client).unwrap().serial, "s9");
+
+    // changing an index moves the objects
+    assert_eq!(firefox.update_all("name", "Firefox ESR").unwrap(), 2);
+    assert_eq!(Query::<Browser>::find("name", "Firefox", &client).ids().unwrap(), vec![]);
+    assert_eq!(Query::<Browser>::find("name", "Firefox ESR", &client).ids().unwrap(), vec![b1.id, b2.id]);
+    assert_eq!(Query::<Browser>::find("name", "Firefox", &client).update_all("name", "Other").unwrap(), 0);
+
+    assert_eq!(firefox.update_all("os", "linux").unwrap_err(), OhmerError::NotUpdatable("os".to_string()));
+    assert_eq!(firefox.update_all("launches", 3).unwrap_err(), OhmerError::NotUpdatable("launches".to_string()));
+    assert_eq!(firefox.update_all("updated_at", 3).unwrap_err(), OhmerError::NotUpdatable("updated_at".to_string()));
+    assert_eq!(firefox.update_all("missing", 3).unwrap_err(), OhmerError::NotUpdatable("missing".to_string()));
+}
+
+#[test]
+fn test_delete_all() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Browser:indices:name:Opera").unwrap();
+    let _:bool = client.del("Tab:indices:url:https://example.com").unwrap();
+
+    let extension = create!(Extension { name: "uBlock".to_string(), }, &client).unwrap();
+    let b1 = create!(Browser { serial: "o1".to_string(), name: "Opera".to_string(), version: "12".to_string(), }, &client).unwrap();
+    let b2 = create!(Browser { serial: "o2".to_string(), name: "Opera".to_string(), }, &client).unwrap();
+    insert!(b1.extensions, extension, &client).unwrap();
+    incr!(b1.launches, &client).unwrap();
+
+    assert_eq!(Query::<Browser>::find("name", "Opera", &client).delete_all().unwrap(), 2);
+
+    for id in [b1.id, b2.id].iter() {
+        let member:bool = client.sismember("Browser:all", *id).unwrap();
+        assert!(!member);
+        for key in [format!("Browser:{}", id), format!("Browser:{}:_indices", id), format!("Browser:{}:launches", id),
+                format!("Browser:extensions:{}", id)].iter() {
+            let exists:bool = client.exists(&**key).unwrap();
+            assert!(!exists, "{} exists", key);
+        }
+    }
+    assert!(with::<Browser, _>("serial", "o1", &client).unwrap().is_none());
+    assert!(!Query::<Browser>::starts_with("version", "12", &client).ids().unwrap().contains(&b1.id));
+    let exists:bool = client.exists("Browser:indices:name:Opera").unwrap();
+    assert!(!exists);
+    assert_eq!(Query::<Browser>::find("name", "Opera", &client).delete_all().unwrap(), 0);
+
+    // an id left in an index after its hash was removed
+    let b3 = create!(Browser { serial: "o3".to_string(), name: "Opera".to_string(), }, &client).unwrap();
+    let _:bool = client.sadd("Browser:indices:name:Opera", 100000).unwrap();
+    assert_eq!(Query::<Browser>::find("name", "Opera", &client).delete_all().unwrap(), 1);
+    let exists:bool = client.exists(format!("Browser:{}", b3.id)).unwrap();
+    assert!(!exists);
+
+    let t1 = create!(Tab { url: "https://example.com".to_string(), }, &client).unwrap();
+    assert_eq!(Query::<Tab>::find("url", "https://example.com", &client).delete_all().unwrap(), 1);
+    assert!(deleted_query::<Tab>(&client).unwrap().ids().unwrap().contains(&t1.id));
+    assert_eq!(restore::<Tab>(t1.id, &client).unwrap().unwrap().id, t1.id);
+    assert!(Query::<Tab>::find("url", "https://example.com", &client).ids().unwrap().contains(&t1.id));
+}
@@ -0,0 +1,57 @@
+extern crate ohmers;
+extern crate redis;
+extern crate rustc_serialize;
+
+use ohmers::{get, Ohmer};
+use redis::Commands;
+use rustc_serialize::Encodable;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Debug)]
+struct Avatar {
+    id: usize,
+    name: String,
+    thumbnail: Vec<u8>,
+    checksum: Option<Vec<u8>>,
+}
+
+impl Default for Avatar {
+    fn default() -> Self {
+        Avatar {
+            id: 0,
+            name: "".to_string(),
+            thumbnail: vec![],
+            checksum: None,
+        }
+    }
+}
+impl Ohmer for Avatar {
+    fn id(&self) -> usize { self.id }
+    fn set_id(&mut self, id: usize) { self.id = id; }
+}
+
+#[test]
+fn test_bytes() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let mut avatar = Avatar::default();
+    avatar.name = "Alice".to_string();
+    avatar.thumbnail = vec![0, 159, 146, 150, 255];
+    avatar.checksum = Some(vec![222, 173, 190, 239]);
+    avatar.save(&client).unwrap();
+
+    let thumbnail: Vec<u8> = client.hget(format!("Avatar:{}", avatar.id), "thumbnail").unwrap();
+    assert_eq!(thumbnail, vec![0, 159, 146, 150, 255]);
+
+    let avatar2 = get(avatar.id, &client).unwrap();
+    assert_eq!(avatar, avatar2);
+}
+
+#[test]
+fn test_bytes_empty() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let mut avatar = Avatar::default();
+    avatar.name = "Bob".to_string();
+    avatar.save(&client).unwrap();
+
+    let avatar2 = get(avatar.id, &client).unwrap();
+    assert_eq!(avatar, avatar2);
+}
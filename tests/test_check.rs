@@ -0,0 +1,152 @@
+#[macro_use(model, create, incr)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{check, Counter, CreatedAt, Ohmer, Set};
+use redis::Commands;
+
+model!(
+    Invoice {
+        uniques { number:String = "".to_string(); };
+        indices {
+            customer:String = "".to_string();
+            created_at:CreatedAt = CreatedAt::new();
+        };
+        prefix { reference:String = "".to_string(); };
+        reminders:Counter = Counter;
+        items:Set<Item> = Set::new();
+        total:u32 = 0;
+    });
+
+model!(
+    Receipt {
+        uniques { number:String = "".to_string(); };
+        indices {
+            customer:String = "".to_string();
+            created_at:CreatedAt = CreatedAt::new();
+        };
+        prefix { reference:String = "".to_string(); };
+        prints:Counter = Counter;
+    });
+
+model!(
+    Item {
+        name:String = "".to_string();
+    });
+
+fn cleanup(class: &str, client: &redis::Client) {
+    let keys:Vec<String> = client.keys(format!("{}:*", class)).unwrap();
+    for key in keys {
+        let _:bool = client.del(key).unwrap();
+    }
+}
+
+#[test]
+fn test_check_clean() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    cleanup("Receipt", &client);
+
+    for i in 0..5 {
+        let receipt = create!(Receipt { number: format!("N{}", i), customer: "ACME".to_string(), reference: format!("R-{}", i), }, &client).unwrap();
+        incr!(receipt.prints, &client).unwrap();
+        receipt.expire(60, &client).unwrap();
+    }
+    let report = check::<Receipt>(2, false, &client).unwrap();
+    assert!(report.is_clean(), "{:?}", report);
+
+    cleanup("Receipt", &client);
+}
+
+#[test]
+fn test_check_report() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    cleanup("Invoice", &client);
+
+    let kept = create!(Invoice { number: "A1".to_string(), customer: "ACME".to_string(), reference: "R-A".to_string(), }, &client).unwrap();
+    let lost = create!(Invoice { number: "B1".to_string(), customer: "Initech".to_string(), reference: "R-B".to_string(), }, &client).unwrap();
+    let twin = create!(Invoice { number: "C1".to_string(), customer: "ACME".to_string(), }, &client).unwrap();
+    let broken = create!(Invoice { number: "D1".to_string(), customer: "ACME".to_string(), }, &client).unwrap();
+    let item = create!(Item { name: "bolt".to_string(), }, &client).unwrap();
+    kept.items.insert("items", &kept, &item, &client).unwrap();
+    lost.items.insert("items", &lost, &item, &client).unwrap();
+    incr!(lost.reminders, &client).unwrap();
+
+    // an object removed from `all`, an id without hash, and a changed unique
+    let _:bool = client.srem("Invoice:all", lost.id).unwrap();
+    let _:bool = client.sadd("Invoice:all", 999).unwrap();
+    let _:() = client.hset(format!("Invoice:{}", twin.id), "number", "A1").unwrap();
+    // and an object that cannot be decoded
+    let _:() = client.hset(format!("Invoice:{}", broken.id), "total", "unknown").unwrap();
+    // sorted sets with an id that does not exist
+    let _:bool = client.zadd("Invoice:sorted:created_at", 998, 1).unwrap();
+    let _:bool = client.zadd("Invoice:prefix:reference", "R-Z:998", 0).unwrap();
+    let _:bool = client.zadd("Invoice:expires", 998, 1).unwrap();
+
+    let report = check::<Invoice>(2, false, &client).unwrap();
+    assert_eq!(report.missing, vec![999]);
+    assert_eq!(report.orphans, vec![lost.id]);
+    assert_eq!(report.dangling_indices, vec![("Invoice:indices:customer:Initech".to_string(), lost.id)]);
+    let mut dangling_uniques = report.dangling_uniques.clone();
+    dangling_uniques.sort();
+    assert_eq!(dangling_uniques, vec![
+        ("Invoice:uniques:number".to_string(), "B1".to_string()),
+    ]);
+    let mut dangling_sorted = report.dangling_sorted.clone();
+    dangling_sorted.sort();
+    let mut expected = vec![
+        ("Invoice:expires".to_string(), "998".to_string()),
+        ("Invoice:prefix:reference".to_string(), format!("R-B:{}", lost.id)),
+        ("Invoice:prefix:reference".to_string(), "R-Z:998".to_string()),
+        ("Invoice:sorted:created_at".to_string(), format!("{}", lost.id)),
+        ("Invoice:sorted:created_at".to_string(), "998".to_string()),
+    ];
+    expected.sort();
+    assert_eq!(dangling_sorted, expected);
+    assert_eq!(report.unique_collisions, vec![("number".to_string(), "A1".to_string(), vec![kept.id, twin.id])]);
+    assert_eq!(report.stale_memos, vec![]);
+    assert_eq!(report.undecodable, vec![broken.id]);
+    let mut orphan_keys = report.orphan_keys.clone();
+    orphan_keys.sort();
+    let mut expected = vec![
+        format!("Invoice:{}:_indices", lost.id),
+        format!("Invoice:{}:_sorted", lost.id),
+        format!("Invoice:{}:_uniques", lost.id),
+        format!("Invoice:{}:reminders", lost.id),
+        format!("Invoice:items:{}", lost.id),
+    ];
+    expected.sort();
+    assert_eq!(orphan_keys, expected);
+
+    // index entries removed by hand leave the memos pointing at them
+    let _:bool = client.srem("Invoice:indices:customer:ACME", kept.id).unwrap();
+    let _:bool = client.zrem("Invoice:prefix:reference", format!("R-A:{}", kept.id)).unwrap();
+    let report = check::<Invoice>(2, true, &client).unwrap();
+    let mut stale_memos = report.stale_memos.clone();
+    stale_memos.sort();
+    assert_eq!(stale_memos, vec![
+        (format!("Invoice:{}:_indices", kept.id), "Invoice:indices:customer:ACME".to_string()),
+        (format!("Invoice:{}:_sorted", kept.id), "Invoice:prefix:reference".to_string()),
+    ]);
+
+    // the orphan is back with its keys and index entries
+    let report = check::<Invoice>(2, false, &client).unwrap();
+    assert_eq!(report.unique_collisions.len(), 1);
+    assert_eq!(report.undecodable, vec![broken.id]);
+    assert_eq!(report.missing, vec![]);
+    assert_eq!(report.orphans, vec![]);
+    assert_eq!(report.orphan_keys, Vec::<String>::new());
+    assert_eq!(report.dangling_indices, vec![]);
+    assert_eq!(report.dangling_uniques, vec![]);
+    assert_eq!(report.dangling_sorted, vec![]);
+    assert_eq!(report.stale_memos, vec![]);
+    let member:bool = client.sismember("Invoice:all", lost.id).unwrap();
+    assert!(member);
+    let exists:bool = client.exists(format!("Invoice:items:{}", lost.id)).unwrap();
+    assert!(exists);
+    let score:Option<f64> = client.zscore("Invoice:sorted:created_at", 998).unwrap();
+    assert_eq!(score, None);
+    let exists:bool = client.exists(format!("Invoice:{}", broken.id)).unwrap();
+    assert!(exists);
+
+    cleanup("Invoice", &client);
+}
@@ -0,0 +1,92 @@
+#[macro_use(model, create, find)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Ohmer, Query, StalSet};
+use redis::Commands;
+
+model!(
+    Release {
+        indices { name:String = "".to_string(); };
+        composite_indices {
+            name, major_version;
+            name, major_version, channel;
+        };
+        normalize { channel: Lowercase; };
+        major_version:u8 = 0;
+        channel:String = "".to_string();
+    });
+
+model!(
+    Build {
+        composite_indices { product, channel; };
+        product:String = "".to_string();
+        channel:String = "".to_string();
+    });
+
+fn set_keys(set: StalSet) -> Vec<Vec<u8>> {
+    match set {
+        StalSet::Inter(sets) => sets.into_iter().map(|set| match set {
+            StalSet::Key(key) => key,
+            _ => panic!("expected a key"),
+        }).collect(),
+        _ => panic!("expected an intersection"),
+    }
+}
+
+#[test]
+fn test_composite_index() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let keys:Vec<String> = client.keys("Release:indices:*").unwrap();
+    for key in keys {
+        let _:bool = client.del(key).unwrap();
+    }
+
+    let r1 = create!(Release { name: "Chrome".to_string(), major_version: 44, channel: "Stable".to_string(), }, &client).unwrap();
+    let r2 = create!(Release { name: "Chrome".to_string(), major_version: 44, channel: "Beta".to_string(), }, &client).unwrap();
+    let mut r3 = create!(Release { name: "Chrome".to_string(), major_version: 45, channel: "Beta".to_string(), }, &client).unwrap();
+
+    let members:Vec<usize> = client.smembers("Release:indices:name+major_version:Chrome:44").unwrap();
+    assert_eq!(members.len(), 2);
+    let member:bool = client.sismember("Release:indices:name+major_version+channel:Chrome:45:beta", r3.id).unwrap();
+    assert!(member);
+
+    assert_eq!(find!(Release { name: "Chrome", major_version: 44, }, &client).ids().unwrap(), vec![r1.id, r2.id]);
+    assert_eq!(find!(Release { major_version: 44, name: "Chrome", channel: "BETA", }, &client).ids().unwrap(), vec![r2.id]);
+    assert_eq!(Query::<Release>::from_keys(&[("name", "Chrome"), ("major_version", "45")], &client).ids().unwrap(), vec![r3.id]);
+
+    // the largest group is used
+    assert_eq!(set_keys(Query::<Release>::keys(&[("name", "Chrome"), ("major_version", "45"), ("channel", "beta")])),
+            vec![b"Release:indices:name+major_version+channel:Chrome:45:beta".to_vec()]);
+    assert_eq!(set_keys(Query::<Release>::keys(&[("name", "Chrome")])),
+            vec![b"Release:indices:name:Chrome".to_vec()]);
+
+    r3.major_version = 44;
+    r3.save(&client).unwrap();
+    assert_eq!(find!(Release { name: "Chrome", major_version: 45, }, &client).ids().unwrap(), vec![]);
+    assert_eq!(find!(Release { name: "Chrome", major_version: 44, }, &client).ids().unwrap(), vec![r1.id, r2.id, r3.id]);
+
+    let id = r1.id;
+    r1.delete(&client).unwrap();
+    let member:bool = client.sismember("Release:indices:name+major_version:Chrome:44", id).unwrap();
+    assert!(!member);
+}
+
+#[test]
+fn test_composite_index_separator() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let keys:Vec<String> = client.keys("Build:indices:*").unwrap();
+    for key in keys {
+        let _:bool = client.del(key).unwrap();
+    }
+
+    let b1 = create!(Build { product: "Edge:4".to_string(), channel: "dev".to_string(), }, &client).unwrap();
+    let b2 = create!(Build { product: "Edge".to_string(), channel: "4:dev".to_string(), }, &client).unwrap();
+
+    let found = |product: &str, channel: &str| find!(Build { product: product, channel: channel, }, &client)
+        .try_into_iter().unwrap().map(|b| b.id).collect::<Vec<_>>();
+    assert_eq!(found("Edge:4", "dev"), vec![b1.id]);
+    assert_eq!(found("Edge", "4:dev"), vec![b2.id]);
+    let member:bool = client.sismember("Build:indices:product+channel:Edge\\:4:dev", b1.id).unwrap();
+    assert!(member);
+}
@@ -0,0 +1,110 @@
+#[macro_use(model, create, new)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{with_composite, Ohmer, OhmerError, Reference};
+use redis::Commands;
+
+model!(
+    Tenant {
+        name:String = "".to_string();
+    });
+
+model!(
+    Account {
+        composite_uniques { tenant_id, email; };
+        normalize { email: Lowercase; };
+        tenant_id:usize = 0;
+        email:String = "".to_string();
+    });
+
+model!(
+    Hall {
+        name:String = "".to_string();
+    });
+
+model!(
+    Booking {
+        composite_uniques { hall, date; };
+        hall:Reference<Hall> = Reference::new();
+        date:Option<String> = None;
+    });
+
+model!(
+    Route {
+        composite_uniques { origin, destination; };
+        origin:String = "".to_string();
+        destination:String = "".to_string();
+    });
+
+#[test]
+fn test_composite_unique() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Account:uniques:tenant_id+email").unwrap();
+
+    let t1 = create!(Tenant { name: "Acme".to_string(), }, &client).unwrap();
+    let t2 = create!(Tenant { name: "Globex".to_string(), }, &client).unwrap();
+
+    let a1 = create!(Account { tenant_id: t1.id, email: "alice@x.com".to_string(), }, &client).unwrap();
+    let a2 = create!(Account { tenant_id: t2.id, email: "alice@x.com".to_string(), }, &client).unwrap();
+
+    let mut a3 = new!(Account { tenant_id: t1.id, email: "Alice@X.com".to_string(), });
+    assert_eq!(a3.save(&client).unwrap_err(), OhmerError::UniqueIndexViolation("tenant_id+email".to_string()));
+
+    let tenant = format!("{}", t2.id);
+    assert_eq!(with_composite::<Account>(&[("tenant_id", &*tenant), ("email", "ALICE@x.com")], &client).unwrap().unwrap().id, a2.id);
+    assert_eq!(with_composite::<Account>(&[("email", "alice@x.com"), ("tenant_id", &*tenant)], &client).unwrap().unwrap().id, a2.id);
+    assert!(with_composite::<Account>(&[("tenant_id", &*tenant), ("email", "bob@x.com")], &client).unwrap().is_none());
+
+    a1.delete(&client).unwrap();
+    a3.save(&client).unwrap();
+    let tenant = format!("{}", t1.id);
+    assert_eq!(with_composite::<Account>(&[("tenant_id", &*tenant), ("email", "alice@x.com")], &client).unwrap().unwrap().id, a3.id);
+}
+
+#[test]
+fn test_composite_unique_reference() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Booking:uniques:hall+date").unwrap();
+
+    let hall = create!(Hall { name: "Main".to_string(), }, &client).unwrap();
+    let mut booking = new!(Booking { date: Some("2015-10-21".to_string()), });
+    booking.hall.set(&hall);
+    booking.save(&client).unwrap();
+
+    let mut other = new!(Booking { date: Some("2015-10-21".to_string()), });
+    other.hall.set(&hall);
+    assert_eq!(other.save(&client).unwrap_err(), OhmerError::UniqueIndexViolation("hall+date".to_string()));
+    other.date = Some("2015-10-22".to_string());
+    other.save(&client).unwrap();
+
+    // without a date the pair is not constrained
+    let mut undated = new!(Booking {});
+    undated.hall.set(&hall);
+    undated.save(&client).unwrap();
+    let mut undated2 = new!(Booking {});
+    undated2.hall.set(&hall);
+    undated2.save(&client).unwrap();
+
+    let hall_id = format!("{}", hall.id);
+    assert_eq!(with_composite::<Booking>(&[("hall", &*hall_id), ("date", "2015-10-22")], &client).unwrap().unwrap().id, other.id);
+}
+
+#[test]
+fn test_composite_unique_separator() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Route:uniques:origin+destination").unwrap();
+
+    let r1 = create!(Route { origin: "a:b".to_string(), destination: "c".to_string(), }, &client).unwrap();
+    let r2 = create!(Route { origin: "a".to_string(), destination: "b:c".to_string(), }, &client).unwrap();
+    let r3 = create!(Route { origin: "a\\".to_string(), destination: ":b".to_string(), }, &client).unwrap();
+    let r4 = create!(Route { origin: "a\\:".to_string(), destination: "b".to_string(), }, &client).unwrap();
+
+    assert_eq!(with_composite::<Route>(&[("origin", "a:b"), ("destination", "c")], &client).unwrap().unwrap().id, r1.id);
+    assert_eq!(with_composite::<Route>(&[("origin", "a"), ("destination", "b:c")], &client).unwrap().unwrap().id, r2.id);
+    assert_eq!(with_composite::<Route>(&[("origin", "a\\"), ("destination", ":b")], &client).unwrap().unwrap().id, r3.id);
+    assert_eq!(with_composite::<Route>(&[("origin", "a\\:"), ("destination", "b")], &client).unwrap().unwrap().id, r4.id);
+
+    let value:Option<usize> = client.hget("Route:uniques:origin+destination", "a\\:b:c").unwrap();
+    assert_eq!(value, Some(r1.id));
+}
@@ -0,0 +1,49 @@
+#[macro_use(model, create)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Ohmer, Query};
+use redis::Commands;
+
+model!(
+    Sensor {
+        indices {
+            zone:String = "".to_string();
+            active:bool = true;
+        };
+        prefix { label:String = "".to_string(); };
+    });
+
+#[test]
+fn test_count() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Sensor:indices:zone:north").unwrap();
+    let _:bool = client.del("Sensor:indices:active:0").unwrap();
+    let _:bool = client.del("Sensor:indices:active:1").unwrap();
+    let _:bool = client.del("Sensor:prefix:label").unwrap();
+
+    let s1 = create!(Sensor { zone: "north".to_string(), label: "temp-1".to_string(), }, &client).unwrap();
+    let s2 = create!(Sensor { zone: "north".to_string(), label: "temp-2".to_string(), active: false, }, &client).unwrap();
+    let s3 = create!(Sensor { zone: "north".to_string(), label: "hum-1".to_string(), }, &client).unwrap();
+
+    let query = Query::<Sensor>::find("zone", "north", &client);
+    assert_eq!(query.count().unwrap(), 3);
+    assert!(!query.is_empty().unwrap());
+    assert_eq!(query.ids().unwrap(), vec![s1.id, s2.id, s3.id]);
+    assert_eq!(query.first().unwrap().unwrap().id, s1.id);
+
+    let mut query = Query::<Sensor>::find("zone", "north", &client);
+    query.inter("active", "1");
+    assert_eq!(query.count().unwrap(), 2);
+    assert_eq!(query.ids().unwrap(), vec![s1.id, s3.id]);
+    query.diff("zone", "north");
+    assert_eq!(query.count().unwrap(), 0);
+    assert!(query.is_empty().unwrap());
+    assert!(query.first().unwrap().is_none());
+    assert_eq!(query.ids().unwrap(), vec![]);
+
+    let query = Query::<Sensor>::starts_with("label", "temp", &client);
+    assert_eq!(query.count().unwrap(), 2);
+    assert_eq!(query.first().unwrap().unwrap().id, s1.id);
+    assert_eq!(query.ids().unwrap(), vec![s1.id, s2.id]);
+}
@@ -0,0 +1,89 @@
+#[macro_use(model, create, new, incr, insert)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use std::thread::sleep;
+use std::time::Duration;
+
+use ohmers::{purge_expired, with, Counter, Ohmer, Query, Set};
+use redis::Commands;
+
+model!(
+    Visitor {
+        name:String = "".to_string();
+    });
+
+model!(
+    Login {
+        uniques { token:String = "".to_string(); };
+        indices { user:String = "".to_string(); };
+        hits:Counter = Counter;
+        visitors:Set<Visitor> = Set::new();
+    });
+
+fn ttl(key: String, client: &redis::Client) -> isize {
+    redis::cmd("TTL").arg(key).query(client).unwrap()
+}
+
+#[test]
+fn test_expire() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Login:uniques:token").unwrap();
+    let _:bool = client.del("Login:indices:user:bob").unwrap();
+    let visitor = create!(Visitor { name: "Alice".to_string(), }, &client).unwrap();
+
+    let mut login = new!(Login { token: "abc123".to_string(), user: "alice".to_string(), });
+    login.save_with_ttl(1, &client).unwrap();
+    incr!(login.hits, &client).unwrap();
+    insert!(login.visitors, visitor, &client).unwrap();
+    login.expire(1, &client).unwrap();
+
+    assert!(ttl(format!("Login:{}", login.id), &client) > 0);
+    assert!(ttl(format!("Login:{}:hits", login.id), &client) > 0);
+    assert!(ttl(format!("Login:visitors:{}", login.id), &client) > 0);
+
+    // saving again keeps the expiration
+    login.user = "bob".to_string();
+    login.save(&client).unwrap();
+    assert!(ttl(format!("Login:{}", login.id), &client) > 0);
+
+    let keeper = create!(Login { token: "def456".to_string(), user: "bob".to_string(), }, &client).unwrap();
+
+    sleep(Duration::from_millis(1100));
+    assert!(purge_expired::<Login>(&client).unwrap() >= 1);
+
+    assert!(with::<Login, _>("token", "abc123", &client).unwrap().is_none());
+    assert!(with::<Login, _>("token", "def456", &client).unwrap().is_some());
+    let ids = Query::<Login>::find("user", "bob", &client).try_into_iter().unwrap().map(|l| l.id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![keeper.id]);
+    let member:bool = client.sismember("Login:all", login.id).unwrap();
+    assert!(!member);
+    let exists:bool = client.exists(format!("Login:{}:_indices", login.id)).unwrap();
+    assert!(!exists);
+
+    assert_eq!(purge_expired::<Login>(&client).unwrap(), 0);
+    keeper.delete(&client).unwrap();
+}
+
+#[test]
+fn test_save_with_ttl() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let mut login = create!(Login { token: "jkl012".to_string(), user: "carol".to_string(), }, &client).unwrap();
+    incr!(login.hits, &client).unwrap();
+    assert_eq!(ttl(format!("Login:{}:hits", login.id), &client), -1);
+
+    login.save_with_ttl(30, &client).unwrap();
+    assert!(ttl(format!("Login:{}", login.id), &client) > 0);
+    assert!(ttl(format!("Login:{}:hits", login.id), &client) > 0);
+    let expires:Option<u64> = client.zscore("Login:expires", login.id).unwrap();
+    assert!(expires.is_some());
+
+    login.delete(&client).unwrap();
+}
+
+#[test]
+fn test_expire_not_saved() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let login = new!(Login { token: "ghi789".to_string(), });
+    assert_eq!(login.expire(10, &client).unwrap_err(), ohmers::OhmerError::NotSaved);
+}
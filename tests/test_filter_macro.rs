@@ -0,0 +1,57 @@
+#[macro_use(model, create, filter, insert)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Ohmer, OhmerError, Set};
+use redis::Commands;
+
+model!(
+    Attendee {
+        indices {
+            role:String = "".to_string();
+            country:String = "".to_string();
+        };
+    });
+
+model!(
+    Meetup {
+        attendees:Set<Attendee> = Set::new();
+        speakers:Set<Attendee> = Set::new();
+    });
+
+#[test]
+fn test_filter_macro() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    for key in ["Attendee:all", "Attendee:indices:role:dev", "Attendee:indices:role:pm", "Attendee:indices:country:AR", "Attendee:indices:country:UY"].iter() {
+        let _:bool = client.del(*key).unwrap();
+    }
+
+    let a = create!(Attendee { role: "dev".to_string(), country: "AR".to_string(), }, &client).unwrap();
+    let b = create!(Attendee { role: "dev".to_string(), country: "UY".to_string(), }, &client).unwrap();
+    let c = create!(Attendee { role: "pm".to_string(), country: "AR".to_string(), }, &client).unwrap();
+    let d = create!(Attendee { role: "pm".to_string(), country: "UY".to_string(), }, &client).unwrap();
+    let meetup = create!(Meetup {}, &client).unwrap();
+    insert!(meetup.attendees, a, &client).unwrap();
+    insert!(meetup.attendees, b, &client).unwrap();
+    insert!(meetup.attendees, c, &client).unwrap();
+    insert!(meetup.speakers, b, &client).unwrap();
+
+    assert_eq!(filter!(Attendee { role: "dev", }, &client).unwrap().ids().unwrap(), vec![a.id, b.id]);
+    assert_eq!(filter!(Attendee !{ role: "dev", }, &client).unwrap().ids().unwrap(), vec![c.id, d.id]);
+    assert_eq!(filter!(Attendee not { role: "dev" }, &client).unwrap().ids().unwrap(), vec![c.id, d.id]);
+
+    // `&&` has precedence over `||`
+    assert_eq!(filter!(Attendee { role: "dev", } && { country: "AR", } || { role: "pm", country: "UY", }, &client)
+            .unwrap().ids().unwrap(), vec![a.id, d.id]);
+    assert_eq!(filter!(Attendee { role: "dev", } && ({ country: "AR", } || { country: "UY", }), &client)
+            .unwrap().ids().unwrap(), vec![a.id, b.id]);
+    assert_eq!(filter!(Attendee !({ role: "dev", } || { country: "UY", }), &client).unwrap().ids().unwrap(), vec![c.id]);
+
+    // relation sets
+    assert_eq!(filter!(Attendee meetup.attendees && !meetup.speakers, &client).unwrap().ids().unwrap(), vec![a.id, c.id]);
+    assert_eq!(filter!(Attendee meetup.speakers || not meetup.attendees && { country: "UY", }, &client)
+            .unwrap().ids().unwrap(), vec![b.id, d.id]);
+
+    let unsaved = Meetup::default();
+    assert_eq!(filter!(Attendee unsaved.attendees, &client).err(), Some(OhmerError::NotSaved));
+}
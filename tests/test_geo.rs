@@ -0,0 +1,65 @@
+#[macro_use(model, create, new)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{all_query, Ohmer, OhmerError, Query};
+use redis::Commands;
+
+model!(
+    Place {
+        geo { location: longitude, latitude; };
+        indices { kind:String = "".to_string(); };
+        name:String = "".to_string();
+        longitude:f64 = 0.0;
+        latitude:f64 = 0.0;
+    });
+
+#[test]
+fn test_geo() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Place:geo:location").unwrap();
+    let _:bool = client.del("Place:indices:kind:museum").unwrap();
+
+    // around the Obelisco, Buenos Aires
+    let (lon, lat) = (-58.3816, -34.6037);
+    let mut teatro = create!(Place { name: "Teatro Colon".to_string(), kind: "theatre".to_string(), longitude: -58.3831, latitude: -34.6011, }, &client).unwrap();
+    let malba = create!(Place { name: "MALBA".to_string(), kind: "museum".to_string(), longitude: -58.4035, latitude: -34.5773, }, &client).unwrap();
+    let louvre = create!(Place { name: "Louvre".to_string(), kind: "museum".to_string(), longitude: 2.3376, latitude: 48.8606, }, &client).unwrap();
+
+    assert_eq!(Query::<Place>::within_radius("location", lon, lat, 1.0, &client).ids().unwrap(), vec![teatro.id]);
+    assert_eq!(Query::<Place>::within_radius("location", lon, lat, 5.0, &client).ids().unwrap(), vec![teatro.id, malba.id]);
+    assert_eq!(Query::<Place>::within_box("location", lon, lat, 8.0, 8.0, &client).ids().unwrap(), vec![teatro.id, malba.id]);
+    assert_eq!(Query::<Place>::within_box("location", lon, lat, 2.0, 8.0, &client).ids().unwrap(), vec![teatro.id]);
+
+    let mut query = Query::<Place>::find("kind", "museum", &client);
+    query.inter_within_radius("location", lon, lat, 5.0);
+    assert_eq!(query.ids().unwrap(), vec![malba.id]);
+
+    let mut query = all_query::<Place>(&client).unwrap();
+    query.inter_within_box("location", 2.35, 48.85, 10.0, 10.0);
+    assert_eq!(query.ids().unwrap(), vec![louvre.id]);
+
+    let names = Query::<Place>::within_radius("location", lon, lat, 5.0, &client)
+        .sort("name", None, true, true).unwrap().map(|p| p.name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["MALBA".to_string(), "Teatro Colon".to_string()]);
+
+    // moving the object updates its location
+    teatro.longitude = 2.2945;
+    teatro.latitude = 48.8584;
+    teatro.save(&client).unwrap();
+    assert_eq!(Query::<Place>::within_radius("location", lon, lat, 1.0, &client).ids().unwrap(), vec![]);
+    assert_eq!(Query::<Place>::within_radius("location", 2.35, 48.85, 10.0, &client).ids().unwrap(), vec![teatro.id, louvre.id]);
+
+    let id = louvre.id;
+    louvre.delete(&client).unwrap();
+    let score:Option<f64> = client.zscore("Place:geo:location", id).unwrap();
+    assert_eq!(score, None);
+}
+
+#[test]
+fn test_geo_invalid_coordinates() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let mut place = new!(Place { name: "Pole".to_string(), longitude: 0.0, latitude: 90.0, });
+    assert_eq!(place.save(&client).unwrap_err(), OhmerError::InvalidCoordinates("location".to_string()));
+    assert_eq!(place.id, 0);
+}
@@ -0,0 +1,241 @@
+#[macro_use(model, create, incr)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{get, with, Counter, Ohmer, OhmerError, Query};
+use ohmers::migration::{Migrations, LOCK_KEY, MIGRATIONS_KEY};
+use std::time::{Duration, Instant};
+
+use redis::Commands;
+
+model!(
+    Shipment {
+        uniques { tracking:String = "".to_string(); };
+        indices { carrier:String = "".to_string(); };
+        weight:u32 = 0;
+    });
+
+model!(
+    Freight {
+        uniques { code:String = "".to_string(); };
+        indices { courier:String = "".to_string(); };
+        prefix { destination:String = "".to_string(); };
+        weight:u32 = 0;
+        fuel:Counter = Counter;
+    });
+
+model!(
+    Haul {
+        uniques { code:String = "".to_string(); };
+        indices { courier:String = "".to_string(); };
+        prefix { destination:String = "".to_string(); };
+        weight:u32 = 0;
+    });
+
+model!(
+    Parcel {
+        indices { courier:String = "".to_string(); };
+        fuel:Counter = Counter;
+    });
+
+model!(
+    Package {
+        indices { courier:String = "".to_string(); };
+    });
+
+model!(
+    Cargo {
+        uniques { code:String = "".to_string(); };
+        indices { courier:String = "".to_string(); };
+        prefix { destination:String = "".to_string(); };
+        weight:u32 = 0;
+        fuel:Counter = Counter;
+    });
+
+fn reset(client: &redis::Client, versions: &[u32], classes: &[&str]) {
+    for version in versions {
+        let _:bool = client.srem(MIGRATIONS_KEY, *version).unwrap();
+    }
+    for class in classes {
+        let keys:Vec<String> = client.keys(format!("{}:*", class)).unwrap();
+        for key in keys {
+            let _:bool = client.del(key).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_migration_versions() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    reset(&client, &[1001, 1002], &[]);
+
+    let mut migrations = Migrations::new();
+    migrations.version(1002);
+    migrations.version(1001);
+    assert_eq!(migrations.pending(&client).unwrap(), vec![1001, 1002]);
+    assert_eq!(migrations.run(&client).unwrap(), vec![1001, 1002]);
+    assert_eq!(migrations.run(&client).unwrap(), vec![]);
+
+    migrations.version(1003);
+    assert_eq!(migrations.pending(&client).unwrap(), vec![1003]);
+    reset(&client, &[1001, 1002, 1003], &[]);
+}
+
+#[test]
+fn test_migration_indices() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    reset(&client, &[2001, 2002, 2003], &["Shipment"]);
+
+    let mut ids = vec![];
+    for i in 0..5 {
+        let shipment = create!(Shipment { tracking: format!("T{}", i), carrier: "UPS".to_string(), }, &client).unwrap();
+        ids.push(shipment.id);
+    }
+    // a field stored by an older version of the model
+    for id in ids.iter() {
+        let _:() = client.hset(format!("Shipment:{}", id), "courier", "DHL").unwrap();
+    }
+    let _:() = client.hdel(format!("Shipment:{}", ids[0]), "weight").unwrap();
+    let _:() = client.hset(format!("Shipment:{}", ids[1]), "weight", 2).unwrap();
+
+    let mut migrations = Migrations::new();
+    migrations.batch(2);
+    migrations.version(2001)
+        .remove_index("Shipment", "carrier")
+        .rename_attribute("Shipment", "courier", "carrier")
+        .add_index("Shipment", "carrier")
+        .backfill("Shipment", "weight", "7");
+    assert_eq!(migrations.run(&client).unwrap(), vec![2001]);
+
+    let mut found = Query::<Shipment>::find("carrier", "DHL", &client).try_into_iter().unwrap().map(|s| s.id).collect::<Vec<_>>();
+    found.sort();
+    assert_eq!(found, ids);
+    assert_eq!(Query::<Shipment>::find("carrier", "UPS", &client).try_iter().unwrap().count(), 0);
+    assert_eq!(get::<Shipment>(ids[0], &client).unwrap().weight, 7);
+    assert_eq!(get::<Shipment>(ids[1], &client).unwrap().weight, 2);
+
+    migrations.version(2002).remove_unique("Shipment", "tracking");
+    migrations.run(&client).unwrap();
+    assert!(with::<Shipment, _>("tracking", "T0", &client).unwrap().is_none());
+
+    // two instances with the same tracking number
+    let _:() = client.hset(format!("Shipment:{}", ids[1]), "tracking", "T0").unwrap();
+    migrations.version(2003).add_unique("Shipment", "tracking");
+    assert_eq!(migrations.run(&client), Err(OhmerError::UniqueIndexViolation("tracking".to_string())));
+    assert_eq!(migrations.pending(&client).unwrap(), vec![2003]);
+    // no batch was indexed before finding the duplicate
+    let exists:bool = client.exists("Shipment:uniques:tracking").unwrap();
+    assert!(!exists);
+    let _:() = client.hset(format!("Shipment:{}", ids[1]), "tracking", "T1").unwrap();
+    migrations.run(&client).unwrap();
+    assert_eq!(with::<Shipment, _>("tracking", "T4", &client).unwrap().unwrap().id, ids[4]);
+
+    reset(&client, &[2001, 2002, 2003], &["Shipment"]);
+}
+
+#[test]
+fn test_migration_unique() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    reset(&client, &[3001], &["Haul"]);
+
+    let haul = create!(Haul { code: "F1".to_string(), courier: "DHL".to_string(), weight: 3, }, &client).unwrap();
+    let _:() = client.hdel(format!("Haul:{}", haul.id), "destination").unwrap();
+
+    let mut migrations = Migrations::new();
+    migrations.version(3001)
+        .rename_attribute("Haul", "code", "reference")
+        .backfill("Haul", "destination", "Lima")
+        .backfill("Haul", "courier", "UPS");
+    migrations.run(&client).unwrap();
+
+    let uniques:Vec<String> = client.hkeys("Haul:uniques:reference").unwrap();
+    assert_eq!(uniques, vec!["F1".to_string()]);
+    let exists:bool = client.exists("Haul:uniques:code").unwrap();
+    assert!(!exists);
+    let hash:std::collections::HashMap<String, String> = client.hgetall(format!("Haul:{}", haul.id)).unwrap();
+    assert_eq!(hash.get("reference"), Some(&"F1".to_string()));
+    assert_eq!(hash.get("code"), None);
+    assert_eq!(hash.get("destination"), Some(&"Lima".to_string()));
+    assert_eq!(hash.get("courier"), Some(&"DHL".to_string()));
+
+    reset(&client, &[3001], &["Haul"]);
+}
+
+#[test]
+fn test_migration_rename_class() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    reset(&client, &[4001], &["Freight", "Cargo"]);
+
+    let mut ids = vec![];
+    for i in 0..4 {
+        let freight = create!(Freight { code: format!("C{}", i), courier: "DHL".to_string(), destination: "Quito".to_string(), }, &client).unwrap();
+        incr!(freight.fuel, 5, &client).unwrap();
+        ids.push(freight.id);
+    }
+
+    let mut migrations = Migrations::new();
+    migrations.batch(3);
+    migrations.version(4001).rename_class("Freight", "Cargo");
+    migrations.run(&client).unwrap();
+
+    let left:Vec<String> = client.keys("Freight:*").unwrap();
+    assert_eq!(left, Vec::<String>::new());
+
+    let cargo = get::<Cargo>(ids[2], &client).unwrap();
+    assert_eq!(cargo.code, "C2");
+    assert_eq!(cargo.destination, "Quito");
+    assert_eq!(cargo.fuel.get(&cargo, "fuel", &client).unwrap(), 5);
+    assert_eq!(with::<Cargo, _>("code", "C1", &client).unwrap().unwrap().id, ids[1]);
+    assert_eq!(Query::<Cargo>::find("courier", "DHL", &client).try_iter().unwrap().count(), 4);
+
+    // memo keys point to the new index keys
+    cargo.delete(&client).unwrap();
+    assert_eq!(Query::<Cargo>::find("courier", "DHL", &client).try_iter().unwrap().count(), 3);
+    assert!(with::<Cargo, _>("code", "C2", &client).unwrap().is_none());
+
+    reset(&client, &[4001], &["Freight", "Cargo"]);
+}
+
+#[test]
+fn test_migration_rename_conflict() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    reset(&client, &[5001], &["Parcel", "Package"]);
+
+    let parcel = create!(Parcel { courier: "DHL".to_string(), }, &client).unwrap();
+    incr!(parcel.fuel, &client).unwrap();
+    let package = create!(Package { courier: "UPS".to_string(), }, &client).unwrap();
+
+    let mut migrations = Migrations::new();
+    migrations.version(5001).rename_class("Parcel", "Package");
+    match migrations.run(&client) {
+        Err(OhmerError::KeyExists(key)) => assert!(key.starts_with("Package:")),
+        result => panic!("unexpected result {:?}", result),
+    }
+    assert_eq!(migrations.pending(&client).unwrap(), vec![5001]);
+
+    // neither model was changed
+    assert_eq!(get::<Parcel>(parcel.id, &client).unwrap().courier, "DHL");
+    assert_eq!(parcel.fuel.get(&parcel, "fuel", &client).unwrap(), 1);
+    assert_eq!(get::<Package>(package.id, &client).unwrap().courier, "UPS");
+
+    reset(&client, &[5001], &["Parcel", "Package"]);
+}
+
+#[test]
+fn test_migration_lock() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    reset(&client, &[6001], &[]);
+
+    // another process holding the lock, released when it expires
+    let _:() = redis::cmd("SET").arg(LOCK_KEY).arg("other").arg("PX").arg(300).query(&client).unwrap();
+    let start = Instant::now();
+    let mut migrations = Migrations::new();
+    migrations.version(6001);
+    assert_eq!(migrations.run(&client).unwrap(), vec![6001]);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    let exists:bool = client.exists(LOCK_KEY).unwrap();
+    assert!(!exists);
+
+    reset(&client, &[6001], &[]);
+}
@@ -94,3 +94,48 @@ fn test_model_unique_index_macro() {
 
     assert_eq!(get::<UIPerson>(person.id, &client).unwrap(), person);
 }
+
+// more fields than the default macro recursion limit
+model!(WideRecord {
+        indices { kind:String = "".to_owned(); };
+        f0:u8 = 0; f1:u8 = 1; f2:u8 = 2; f3:u8 = 3; f4:u8 = 4;
+        f5:u8 = 5; f6:u8 = 6; f7:u8 = 7; f8:u8 = 8; f9:u8 = 9;
+        f10:u8 = 0; f11:u8 = 1; f12:u8 = 2; f13:u8 = 3; f14:u8 = 4;
+        f15:u8 = 5; f16:u8 = 6; f17:u8 = 7; f18:u8 = 8; f19:u8 = 9;
+        f20:u8 = 0; f21:u8 = 1; f22:u8 = 2; f23:u8 = 3; f24:u8 = 4;
+        f25:u8 = 5; f26:u8 = 6; f27:u8 = 7; f28:u8 = 8; f29:u8 = 9;
+        f30:u8 = 0; f31:u8 = 1; f32:u8 = 2; f33:u8 = 3; f34:u8 = 4;
+        f35:u8 = 5; f36:u8 = 6; f37:u8 = 7; f38:u8 = 8; f39:u8 = 9;
+        f40:u8 = 0; f41:u8 = 1; f42:u8 = 2; f43:u8 = 3; f44:u8 = 4;
+        f45:u8 = 5; f46:u8 = 6; f47:u8 = 7; f48:u8 = 8; f49:u8 = 9;
+        f50:u8 = 0; f51:u8 = 1; f52:u8 = 2; f53:u8 = 3; f54:u8 = 4;
+        f55:u8 = 5; f56:u8 = 6; f57:u8 = 7; f58:u8 = 8; f59:u8 = 9;
+        f60:u8 = 0; f61:u8 = 1; f62:u8 = 2; f63:u8 = 3; f64:u8 = 4;
+        f65:u8 = 5; f66:u8 = 6; f67:u8 = 7; f68:u8 = 8; f69:u8 = 9;
+        f70:u8 = 0; f71:u8 = 1; f72:u8 = 2; f73:u8 = 3; f74:u8 = 4;
+        f75:u8 = 5; f76:u8 = 6; f77:u8 = 7; f78:u8 = 8; f79:u8 = 9;
+        f80:u8 = 0; f81:u8 = 1; f82:u8 = 2; f83:u8 = 3; f84:u8 = 4;
+        f85:u8 = 5; f86:u8 = 6; f87:u8 = 7; f88:u8 = 8; f89:u8 = 9;
+        f90:u8 = 0; f91:u8 = 1; f92:u8 = 2; f93:u8 = 3; f94:u8 = 4;
+        f95:u8 = 5; f96:u8 = 6; f97:u8 = 7; f98:u8 = 8; f99:u8 = 9;
+        f100:u8 = 0; f101:u8 = 1; f102:u8 = 2; f103:u8 = 3; f104:u8 = 4;
+        f105:u8 = 5; f106:u8 = 6; f107:u8 = 7; f108:u8 = 8; f109:u8 = 9;
+        f110:u8 = 0; f111:u8 = 1; f112:u8 = 2; f113:u8 = 3; f114:u8 = 4;
+        f115:u8 = 5; f116:u8 = 6; f117:u8 = 7; f118:u8 = 8; f119:u8 = 9;
+        f120:u8 = 0; f121:u8 = 1; f122:u8 = 2; f123:u8 = 3; f124:u8 = 4;
+        f125:u8 = 5; f126:u8 = 6; f127:u8 = 7; f128:u8 = 8; f129:u8 = 9;
+        f130:u8 = 0; f131:u8 = 1; f132:u8 = 2; f133:u8 = 3; f134:u8 = 4;
+        f135:u8 = 5; f136:u8 = 6; f137:u8 = 7; f138:u8 = 8; f139:u8 = 9;
+        });
+
+#[test]
+fn test_model_many_fields() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+
+    let mut record = WideRecord::default();
+    record.f139 = 42;
+    record.save(&client).unwrap();
+    let record = get::<WideRecord>(record.id, &client).unwrap();
+    assert_eq!(record.f1, 1);
+    assert_eq!(record.f139, 42);
+}
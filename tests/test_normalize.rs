@@ -0,0 +1,71 @@
+#[macro_use(model, create, find)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{with, Ohmer, OhmerError, Query};
+use redis::Commands;
+
+fn digits(value: &str) -> String {
+    value.chars().filter(|c| c.is_digit(10)).collect()
+}
+
+model!(
+    Member {
+        uniques {
+            email:String = "".to_string();
+            phone:String = "".to_string();
+        };
+        indices { city:String = "".to_string(); };
+        normalize {
+            email: Trim, Lowercase;
+            phone: Custom(digits);
+            city: Nfkc, Lowercase;
+        };
+        name:String = "".to_string();
+    });
+
+#[test]
+fn test_normalize_uniques() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Member:uniques:email").unwrap();
+    let _:bool = client.del("Member:uniques:phone").unwrap();
+
+    let member = create!(Member { email: "Alice@X.com".to_string(), phone: "555-1234".to_string(), }, &client).unwrap();
+
+    // the stored value is kept as given
+    let stored:String = client.hget(format!("Member:{}", member.id), "email").unwrap();
+    assert_eq!(&*stored, "Alice@X.com");
+
+    assert_eq!(with::<Member, _>("email", "alice@x.com ", &client).unwrap().unwrap().id, member.id);
+    assert_eq!(with::<Member, _>("phone", "(555) 1234", &client).unwrap().unwrap().id, member.id);
+    assert!(with::<Member, _>("email", "bob@x.com", &client).unwrap().is_none());
+
+    let mut other = Member::default();
+    other.email = " ALICE@x.com".to_string();
+    other.phone = "555-0000".to_string();
+    assert_eq!(other.save(&client).unwrap_err(), OhmerError::UniqueIndexViolation("email".to_string()));
+
+    member.delete(&client).unwrap();
+    other.save(&client).unwrap();
+    assert_eq!(with::<Member, _>("email", "alice@x.com", &client).unwrap().unwrap().id, other.id);
+}
+
+#[test]
+fn test_normalize_indices() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Member:indices:city:paris").unwrap();
+    // only the values of this test, the hashes are shared with the other one
+    let _:bool = client.hdel("Member:uniques:email", "carol@y.com").unwrap();
+    let _:bool = client.hdel("Member:uniques:phone", "7770001").unwrap();
+
+    let member = create!(Member { email: "Carol@Y.com".to_string(), phone: "777-0001".to_string(), city: "PARIS".to_string(), }, &client).unwrap();
+
+    let ids = find!(Member { city: "Paris", }, &client).try_into_iter().unwrap().map(|m| m.id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![member.id]);
+    let ids = Query::<Member>::find("city", "\u{FF30}aris", &client).try_into_iter().unwrap().map(|m| m.id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![member.id]);
+    let member_of:bool = client.sismember("Member:indices:city:paris", member.id).unwrap();
+    assert!(member_of);
+
+    member.delete(&client).unwrap();
+}
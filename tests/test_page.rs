@@ -0,0 +1,142 @@
+#[macro_use(model, create, incr)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Counter, CreatedAt, Ohmer, OhmerError, Page, Query};
+use redis::Commands;
+
+model!(
+    Song {
+        indices { genre:String = "".to_string(); };
+        title:String = "".to_string();
+        plays:Counter = Counter;
+    });
+
+model!(
+    Post {
+        indices {
+            topic:String = "".to_string();
+            created_at:CreatedAt = CreatedAt::new();
+        };
+        rating:f64 = 0.0;
+    });
+
+fn ids(page: &Page<Song>) -> Vec<usize> {
+    page.items.iter().map(|s| s.id).collect()
+}
+
+fn post_ids(page: &Page<Post>) -> Vec<usize> {
+    page.items.iter().map(|p| p.id).collect()
+}
+
+#[test]
+fn test_page() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Song:indices:genre:jazz").unwrap();
+
+    let mut songs = (0..7).map(|i| create!(Song { genre: "jazz".to_string(), title: format!("Song {}", i), }, &client).unwrap())
+        .collect::<Vec<_>>();
+    let all = songs.iter().map(|s| s.id).collect::<Vec<_>>();
+
+    let query = Query::<Song>::find("genre", "jazz", &client);
+    let page1 = query.page(None, 3).unwrap();
+    assert_eq!(ids(&page1), &all[0..3]);
+    let page2 = query.page(page1.next.as_ref().map(|c| &**c), 3).unwrap();
+    assert_eq!(ids(&page2), &all[3..6]);
+
+    // pages are stable when elements are removed or added
+    songs.remove(4).delete(&client).unwrap();
+    songs.remove(0).delete(&client).unwrap();
+    let new = create!(Song { genre: "jazz".to_string(), }, &client).unwrap();
+    let page2 = query.page(page1.next.as_ref().map(|c| &**c), 3).unwrap();
+    assert_eq!(ids(&page2), vec![all[3], all[5], all[6]]);
+    let page3 = query.page(page2.next.as_ref().map(|c| &**c), 3).unwrap();
+    assert_eq!(ids(&page3), vec![new.id]);
+    assert_eq!(page3.next, None);
+
+    let empty = Query::<Song>::find("genre", "polka", &client).page(None, 3).unwrap();
+    assert_eq!(empty.items.len(), 0);
+    assert_eq!(empty.next, None);
+
+    for cursor in ["12", "x:1", ":1", ""].iter() {
+        assert_eq!(query.page(Some(cursor), 3).unwrap_err(), OhmerError::InvalidCursor(cursor.to_string()));
+    }
+}
+
+#[test]
+fn test_sort_page() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Song:indices:genre:rock").unwrap();
+
+    let a = create!(Song { genre: "rock".to_string(), title: "Bravo".to_string(), }, &client).unwrap();
+    let b = create!(Song { genre: "rock".to_string(), title: "Alpha".to_string(), }, &client).unwrap();
+    let c = create!(Song { genre: "rock".to_string(), title: "Bravo".to_string(), }, &client).unwrap();
+    let d = create!(Song { genre: "rock".to_string(), title: "Charlie".to_string(), }, &client).unwrap();
+    incr!(a.plays, 5, &client).unwrap();
+    incr!(c.plays, 9, &client).unwrap();
+    incr!(d.plays, 1, &client).unwrap();
+
+    let query = Query::<Song>::find("genre", "rock", &client);
+    let page = query.sort_page("title", None, 2, true, true).unwrap();
+    assert_eq!(ids(&page), vec![b.id, a.id]);
+    let page = query.sort_page("title", page.next.as_ref().map(|c| &**c), 2, true, true).unwrap();
+    assert_eq!(ids(&page), vec![c.id, d.id]);
+    assert_eq!(page.next, None);
+
+    let page = query.sort_page("plays", None, 3, false, false).unwrap();
+    assert_eq!(ids(&page), vec![c.id, a.id, d.id]);
+    let page = query.sort_page("plays", page.next.as_ref().map(|c| &**c), 3, false, false).unwrap();
+    assert_eq!(ids(&page), vec![b.id]);
+}
+
+#[test]
+fn test_sort_page_precision() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Post:indices:topic:math").unwrap();
+
+    // equal when printed with 14 digits
+    let a = create!(Post { topic: "math".to_string(), rating: 1.0000000000000004, }, &client).unwrap();
+    let b = create!(Post { topic: "math".to_string(), rating: 1.0000000000000002, }, &client).unwrap();
+    let c = create!(Post { topic: "math".to_string(), rating: 1.0, }, &client).unwrap();
+
+    let query = Query::<Post>::find("topic", "math", &client);
+    let page = query.sort_page("rating", None, 1, false, false).unwrap();
+    assert_eq!(post_ids(&page), vec![a.id]);
+    let page = query.sort_page("rating", page.next.as_ref().map(|c| &**c), 1, false, false).unwrap();
+    assert_eq!(post_ids(&page), vec![b.id]);
+    let page = query.sort_page("rating", page.next.as_ref().map(|c| &**c), 1, false, false).unwrap();
+    assert_eq!(post_ids(&page), vec![c.id]);
+    assert_eq!(page.next, None);
+}
+
+#[test]
+fn test_sort_page_sorted_set() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Post:indices:topic:news").unwrap();
+
+    let posts = (0..5).map(|_| create!(Post { topic: "news".to_string(), }, &client).unwrap()).collect::<Vec<_>>();
+    let other = create!(Post { topic: "sports".to_string(), }, &client).unwrap();
+    // two posts created in the same second, and one in another topic between them
+    for (post, time) in posts.iter().zip([30, 10, 20, 20, 40].iter()) {
+        let _:() = client.zadd("Post:sorted:created_at", post.id, *time).unwrap();
+    }
+    let _:() = client.zadd("Post:sorted:created_at", other.id, 25).unwrap();
+
+    let query = Query::<Post>::find("topic", "news", &client);
+    let page = query.sort_page("created_at", None, 2, true, false).unwrap();
+    assert_eq!(post_ids(&page), vec![posts[1].id, posts[2].id]);
+    let page = query.sort_page("created_at", page.next.as_ref().map(|c| &**c), 2, true, false).unwrap();
+    assert_eq!(post_ids(&page), vec![posts[3].id, posts[0].id]);
+    let page = query.sort_page("created_at", page.next.as_ref().map(|c| &**c), 2, true, false).unwrap();
+    assert_eq!(post_ids(&page), vec![posts[4].id]);
+    assert_eq!(page.next, None);
+
+    let page = query.sort_page("created_at", None, 3, false, false).unwrap();
+    assert_eq!(post_ids(&page), vec![posts[4].id, posts[0].id, posts[2].id]);
+    let page = query.sort_page("created_at", page.next.as_ref().map(|c| &**c), 3, false, false).unwrap();
+    assert_eq!(post_ids(&page), vec![posts[3].id, posts[1].id]);
+
+    // the value of the cursor is a score of the sorted set
+    let cursor = format!("{}:soon", posts[0].id);
+    assert_eq!(query.sort_page("created_at", Some(&cursor), 2, true, false).unwrap_err(), OhmerError::InvalidCursor(cursor.clone()));
+}
@@ -0,0 +1,44 @@
+#[macro_use(model, create)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Ohmer, Query};
+use redis::Commands;
+
+model!(
+    Browser {
+        prefix { name:String = "".to_string(); };
+        indices { vendor:String = "".to_string(); };
+        major_version:u8 = 0;
+    });
+
+#[test]
+fn test_starts_with() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Browser:prefix:name").unwrap();
+    let _:bool = client.del("Browser:indices:vendor:Google").unwrap();
+
+    let chrome = create!(Browser { name: "Chrome".to_string(), vendor: "Google".to_string(), }, &client).unwrap();
+    let chromium = create!(Browser { name: "Chromium".to_string(), vendor: "Google".to_string(), }, &client).unwrap();
+    let mut firefox = create!(Browser { name: "Firefox".to_string(), vendor: "Mozilla".to_string(), }, &client).unwrap();
+
+    assert_eq!(Query::<Browser>::starts_with("name", "Chr", &client).ids().unwrap(), vec![chrome.id, chromium.id]);
+    assert_eq!(Query::<Browser>::starts_with("name", "Chrome", &client).ids().unwrap(), vec![chrome.id]);
+    assert_eq!(Query::<Browser>::starts_with("name", "Safari", &client).ids().unwrap(), vec![]);
+    assert_eq!(Query::<Browser>::starts_with("name", "", &client).ids().unwrap(), vec![chrome.id, chromium.id, firefox.id]);
+
+    let mut query = Query::<Browser>::find("vendor", "Google", &client);
+    query.inter_starts_with("name", "Chromi");
+    assert_eq!(query.ids().unwrap(), vec![chromium.id]);
+
+    firefox.name = "Chrome Canary".to_string();
+    firefox.save(&client).unwrap();
+    assert_eq!(Query::<Browser>::starts_with("name", "F", &client).ids().unwrap(), vec![]);
+    assert_eq!(Query::<Browser>::starts_with("name", "Chrome", &client).ids().unwrap(), vec![chrome.id, firefox.id]);
+
+    let id = chrome.id;
+    chrome.delete(&client).unwrap();
+    assert_eq!(Query::<Browser>::starts_with("name", "Chrome", &client).ids().unwrap(), vec![firefox.id]);
+    let score:Option<u8> = client.zscore("Browser:prefix:name", format!("Chrome:{}", id)).unwrap();
+    assert_eq!(score, None);
+}
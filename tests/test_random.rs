@@ -0,0 +1,81 @@
+#[macro_use(model, create, insert, len, random, pop_random)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use std::collections::HashSet;
+
+use ohmers::{Ohmer, Query, Set};
+use redis::Commands;
+
+model!(
+    Raffle {
+        entries:Set<Entrant> = Set::new();
+    });
+
+model!(
+    Entrant {
+        indices { country:String = "".to_string(); };
+    });
+
+#[test]
+fn test_sample() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Entrant:indices:country:NZ").unwrap();
+    let _:bool = client.del("Entrant:indices:country:AU").unwrap();
+
+    let mut nz = HashSet::new();
+    for _ in 0..5 {
+        nz.insert(create!(Entrant { country: "NZ".to_string(), }, &client).unwrap().id);
+    }
+    let au = create!(Entrant { country: "AU".to_string(), }, &client).unwrap();
+
+    let query = Query::<Entrant>::find("country", "NZ", &client);
+    let sample = query.sample(3, false).unwrap().map(|e| e.id).collect::<Vec<_>>();
+    assert_eq!(sample.len(), 3);
+    assert_eq!(sample.iter().collect::<HashSet<_>>().len(), 3);
+    assert!(sample.iter().all(|id| nz.contains(id)));
+    assert_eq!(query.sample(10, false).unwrap().map(|e| e.id).collect::<HashSet<_>>(), nz);
+
+    let sample = query.sample(20, true).unwrap().map(|e| e.id).collect::<Vec<_>>();
+    assert_eq!(sample.len(), 20);
+    assert!(sample.iter().all(|id| nz.contains(id)));
+
+    let mut query = Query::<Entrant>::find("country", "NZ", &client);
+    query.union("country", "AU");
+    assert_eq!(query.sample(10, false).unwrap().count(), 6);
+    let query = Query::<Entrant>::find("country", "AU", &client);
+    assert_eq!(query.sample(3, true).unwrap().map(|e| e.id).collect::<Vec<_>>(), vec![au.id, au.id, au.id]);
+
+    assert_eq!(Query::<Entrant>::find("country", "AQ", &client).sample(3, true).unwrap().count(), 0);
+    assert_eq!(query.sample(0, false).unwrap().count(), 0);
+}
+
+#[test]
+fn test_random() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let raffle = create!(Raffle {}, &client).unwrap();
+    let mut ids = HashSet::new();
+    for _ in 0..4 {
+        let entrant = create!(Entrant {}, &client).unwrap();
+        insert!(raffle.entries, entrant, &client).unwrap();
+        ids.insert(entrant.id);
+    }
+
+    let picked = random!(raffle.entries, 2, client).unwrap().map(|e| e.id).collect::<HashSet<_>>();
+    assert_eq!(picked.len(), 2);
+    assert!(picked.is_subset(&ids));
+    let picked = raffle.entries.random("entries", &raffle, 8, true, &client).unwrap().collect::<Vec<_>>();
+    assert_eq!(picked.len(), 8);
+    assert_eq!(len!(raffle.entries, client).unwrap(), 4);
+
+    let mut popped = HashSet::new();
+    while let Some(entrant) = pop_random!(raffle.entries, client).unwrap() {
+        popped.insert(entrant.id);
+    }
+    assert_eq!(popped, ids);
+    assert_eq!(len!(raffle.entries, client).unwrap(), 0);
+    assert_eq!(random!(raffle.entries, 2, client).unwrap().count(), 0);
+
+    let unsaved = Raffle::default();
+    assert_eq!(pop_random!(unsaved.entries, client).unwrap_err(), ohmers::OhmerError::NotSaved);
+}
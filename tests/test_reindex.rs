@@ -0,0 +1,108 @@
+#[macro_use(model, create)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{get, reindex, with, Ohmer, OhmerError, Query, UpdatedAt};
+use redis::Commands;
+
+model!(
+    Painting {
+        uniques { catalog:String = "".to_string(); };
+        indices {
+            museum:String = "".to_string();
+            updated_at:UpdatedAt = UpdatedAt::new();
+        };
+        prefix { title:String = "".to_string(); };
+        year:u16 = 0;
+    });
+
+model!(
+    Sculpture {
+        uniques { catalog:String = "".to_string(); };
+        year:u16 = 0;
+    });
+
+fn cleanup(class: &str, client: &redis::Client) {
+    let keys:Vec<String> = client.keys(format!("{}:*", class)).unwrap();
+    for key in keys {
+        let _:bool = client.del(key).unwrap();
+    }
+}
+
+fn museum(name: &str, client: &redis::Client) -> Vec<usize> {
+    let mut ids = Query::<Painting>::find("museum", name, client).ids().unwrap();
+    ids.sort();
+    ids
+}
+
+#[test]
+fn test_reindex() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    cleanup("Painting", &client);
+
+    let mut ids = vec![];
+    for i in 0..7 {
+        let painting = create!(Painting {
+            catalog: format!("P{}", i),
+            museum: "Louvre".to_string(),
+            title: format!("Study {}", i),
+        }, &client).unwrap();
+        ids.push(painting.id);
+    }
+    let updated_at = get::<Painting>(ids[0], &client).unwrap().updated_at;
+
+    // indices lost, and a value changed without updating them
+    let _:bool = client.del("Painting:indices:museum:Louvre").unwrap();
+    let _:bool = client.del("Painting:uniques:catalog").unwrap();
+    let _:bool = client.del("Painting:prefix:title").unwrap();
+    let _:() = client.hset(format!("Painting:{}", ids[3]), "museum", "Prado").unwrap();
+    assert_eq!(museum("Louvre", &client), vec![]);
+
+    assert_eq!(reindex::<Painting>(3, &client).unwrap(), vec![]);
+
+    let mut louvre = ids.clone();
+    louvre.remove(3);
+    assert_eq!(museum("Louvre", &client), louvre);
+    assert_eq!(museum("Prado", &client), vec![ids[3]]);
+    assert_eq!(with::<Painting, _>("catalog", "P5", &client).unwrap().unwrap().id, ids[5]);
+    let titles:Vec<String> = client.zrange("Painting:prefix:title", 0, -1).unwrap();
+    assert_eq!(titles.len(), 7);
+
+    // the hash is not saved again
+    assert_eq!(get::<Painting>(ids[0], &client).unwrap().updated_at, updated_at);
+
+    cleanup("Painting", &client);
+}
+
+#[test]
+fn test_reindex_conflicts() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    cleanup("Sculpture", &client);
+
+    let first = create!(Sculpture { catalog: "A1".to_string(), }, &client).unwrap();
+    let second = create!(Sculpture { catalog: "B1".to_string(), }, &client).unwrap();
+    let _:() = client.hset(format!("Sculpture:{}", second.id), "catalog", "A1").unwrap();
+    // a unique held by an object that no longer exists
+    let _:() = client.hset("Sculpture:uniques:catalog", "C1", 1000).unwrap();
+    let third = create!(Sculpture { catalog: "C2".to_string(), }, &client).unwrap();
+    let _:() = client.hset(format!("Sculpture:{}", third.id), "catalog", "C1").unwrap();
+
+    // an object that cannot be decoded
+    let broken = create!(Sculpture { catalog: "D1".to_string(), }, &client).unwrap();
+    let _:() = client.hset(format!("Sculpture:{}", broken.id), "year", "unknown").unwrap();
+
+    let mut conflicts = reindex::<Sculpture>(10, &client).unwrap();
+    assert_eq!(conflicts.len(), 2);
+    let position = conflicts.iter().position(|&(id, _)| id == broken.id).unwrap();
+    assert_eq!(conflicts.remove(position).1, OhmerError::DecoderError);
+    let (id, ref error) = conflicts[0];
+    assert!(id == first.id || id == second.id);
+    assert_eq!(*error, OhmerError::UniqueIndexViolation("catalog".to_string()));
+
+    let holder = with::<Sculpture, _>("catalog", "A1", &client).unwrap().unwrap();
+    assert!(holder.id == first.id || holder.id == second.id);
+    assert!(holder.id != id);
+    assert_eq!(with::<Sculpture, _>("catalog", "C1", &client).unwrap().unwrap().id, third.id);
+
+    cleanup("Sculpture", &client);
+}
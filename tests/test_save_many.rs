@@ -0,0 +1,105 @@
+#[macro_use(model, new)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{get, save_many, with, OhmerError, Query, UpdatedAt};
+use redis::Commands;
+
+model!(
+    Employee {
+        uniques { badge:String = "".to_string(); };
+        indices { team:String = "".to_string(); };
+        name:String = "".to_string();
+        updated_at:UpdatedAt = UpdatedAt::new();
+    });
+
+model!(
+    Office {
+        geo { location: lon, lat; };
+        lon:String = "".to_string();
+        lat:String = "".to_string();
+    });
+
+fn cleanup(client: &redis::Client) {
+    let _:bool = client.del("Employee:uniques:badge").unwrap();
+    let _:bool = client.del("Employee:indices:team:ops").unwrap();
+}
+
+#[test]
+fn test_save_many() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    cleanup(&client);
+
+    let mut employees = (0..100).map(|i| new!(Employee {
+                badge: format!("B{}", i),
+                team: "ops".to_string(),
+                name: format!("Employee {}", i),
+                })).collect::<Vec<_>>();
+    let results = save_many(&mut employees, false, &client).unwrap();
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert!(employees.iter().all(|e| e.id > 0 && e.updated_at.time() > 0));
+    assert_eq!(Query::<Employee>::find("team", "ops", &client).count().unwrap(), 100);
+    assert_eq!(&*get::<Employee>(employees[42].id, &client).unwrap().name, "Employee 42");
+
+    // updates keep the ids
+    let id = employees[0].id;
+    employees[0].name = "Renamed".to_string();
+    let results = save_many(&mut employees[..1], false, &client).unwrap();
+    assert_eq!(results, vec![Ok(())]);
+    assert_eq!(employees[0].id, id);
+    assert_eq!(&*with::<Employee, _>("badge", "B0", &client).unwrap().unwrap().name, "Renamed");
+
+    let mut batch = vec![
+        new!(Employee { badge: "B1".to_string(), }),
+        new!(Employee { badge: "C1".to_string(), }),
+        new!(Employee { badge: "C1".to_string(), }),
+        new!(Employee { badge: "C2".to_string(), }),
+    ];
+    let results = save_many(&mut batch, false, &client).unwrap();
+    assert_eq!(results, vec![
+        Err(OhmerError::UniqueIndexViolation("badge".to_string())),
+        Ok(()),
+        Err(OhmerError::UniqueIndexViolation("badge".to_string())),
+        Ok(()),
+    ]);
+    assert_eq!(batch.iter().map(|e| e.id > 0).collect::<Vec<_>>(), vec![false, true, false, true]);
+    assert_eq!(with::<Employee, _>("badge", "C1", &client).unwrap().unwrap().id, batch[1].id);
+}
+
+#[test]
+fn test_save_many_abort() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Employee:uniques:badge").unwrap();
+
+    // duplicates in the batch
+    let mut batch = vec![
+        new!(Employee { badge: "D1".to_string(), }),
+        new!(Employee { badge: "D2".to_string(), }),
+        new!(Employee { badge: "D1".to_string(), }),
+    ];
+    assert_eq!(save_many(&mut batch, true, &client).unwrap_err(), OhmerError::UniqueIndexViolation("badge".to_string()));
+    assert!(batch.iter().all(|e| e.id == 0));
+    assert!(with::<Employee, _>("badge", "D2", &client).unwrap().is_none());
+
+    batch.pop();
+    assert_eq!(save_many(&mut batch, true, &client).unwrap(), vec![Ok(()), Ok(())]);
+    assert!(batch.iter().all(|e| e.id > 0));
+
+    // unique already in use
+    let mut batch = vec![new!(Employee { badge: "D3".to_string(), }), new!(Employee { badge: "D2".to_string(), })];
+    assert!(save_many(&mut batch, true, &client).is_err());
+    assert!(with::<Employee, _>("badge", "D3", &client).unwrap().is_none());
+
+    let mut offices = vec![
+        new!(Office { lon: "-58.38".to_string(), lat: "-34.60".to_string(), }),
+        new!(Office { lon: "200".to_string(), lat: "0".to_string(), }),
+    ];
+    assert_eq!(save_many(&mut offices, true, &client).unwrap_err(), OhmerError::InvalidCoordinates("location".to_string()));
+    assert_eq!(offices[0].id, 0);
+    let results = save_many(&mut offices, false, &client).unwrap();
+    assert_eq!(results[1], Err(OhmerError::InvalidCoordinates("location".to_string())));
+    assert!(offices[0].id > 0);
+
+    let mut empty:Vec<Employee> = vec![];
+    assert_eq!(save_many(&mut empty, true, &client).unwrap(), vec![]);
+}
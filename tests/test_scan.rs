@@ -0,0 +1,90 @@
+#[macro_use(model, create, insert)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use std::collections::HashSet;
+
+use ohmers::{scan, Ohmer, OhmerError, Query, Set};
+use redis::Commands;
+
+model!(
+    Sensor {
+        indices { zone:String = "".to_string(); };
+        serial:usize = 0;
+    });
+
+model!(
+    Beacon {
+        serial:usize = 0;
+    });
+
+model!(
+    Gateway {
+        sensors:Set<Sensor> = Set::new();
+        beacons:Set<Beacon> = Set::new();
+    });
+
+#[test]
+fn test_scan() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Sensor:all").unwrap();
+    let _:bool = client.del("Sensor:indices:zone:north").unwrap();
+    let _:bool = client.del("Sensor:indices:zone:south").unwrap();
+
+    let gateway = create!(Gateway {}, &client).unwrap();
+    let mut serials = HashSet::new();
+    for serial in 0..250 {
+        let zone = if serial % 2 == 0 { "north" } else { "south" };
+        let sensor = create!(Sensor { zone: zone.to_string(), serial: serial, }, &client).unwrap();
+        if serial < 30 {
+            insert!(gateway.sensors, sensor, &client).unwrap();
+        }
+        serials.insert(serial);
+    }
+
+    let scanned = scan::<Sensor>(20, &client).unwrap().map(|s| s.unwrap().serial).collect::<Vec<_>>();
+    assert_eq!(scanned.len(), 250);
+    assert_eq!(scanned.into_iter().collect::<HashSet<_>>(), serials);
+
+    let north = Query::<Sensor>::find("zone", "north", &client).scan(7).unwrap();
+    assert!(north.map(|s| s.unwrap().serial).all(|serial| serial % 2 == 0));
+    assert_eq!(Query::<Sensor>::find("zone", "north", &client).scan(7).unwrap().count(), 125);
+
+    let query = gateway.sensors.query("sensors", &gateway, &client).unwrap();
+    assert_eq!(query.scan(1).unwrap().count(), 30);
+
+    // compound queries need to be stored first
+    let mut query = Query::<Sensor>::find("zone", "north", &client);
+    query.union("zone", "south");
+    assert_eq!(query.scan(10).err(), Some(OhmerError::NotStored));
+    assert_eq!(query.store("scan:sensors", 60).unwrap().scan(10).unwrap().count(), 250);
+
+    let _:bool = client.del("scan:sensors").unwrap();
+    assert_eq!(Query::<Sensor>::find("zone", "east", &client).scan(10).unwrap().count(), 0);
+    assert!(scan::<Sensor>(0, &client).is_err());
+}
+
+#[test]
+fn test_scan_deleted() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let gateway = create!(Gateway {}, &client).unwrap();
+    let mut ids = vec![];
+    for serial in 0..10 {
+        let beacon = create!(Beacon { serial: serial, }, &client).unwrap();
+        insert!(gateway.beacons, beacon, &client).unwrap();
+        ids.push(beacon.id);
+    }
+    // the set still has an id whose object is gone
+    let _:bool = client.del(format!("Beacon:{}", ids[3])).unwrap();
+
+    let query = gateway.beacons.query("beacons", &gateway, &client).unwrap();
+    let scanned = query.scan(4).unwrap().map(|b| b.unwrap().id).collect::<HashSet<_>>();
+    assert_eq!(scanned.len(), 9);
+    assert!(!scanned.contains(&ids[3]));
+
+    // an object that cannot be decoded is reported and skipped
+    let _:() = client.hset(format!("Beacon:{}", ids[5]), "serial", "five").unwrap();
+    let (ok, err):(Vec<_>, Vec<_>) = query.scan(4).unwrap().partition(|b| b.is_ok());
+    assert_eq!(ok.len(), 8);
+    assert_eq!(err.into_iter().map(|b| b.unwrap_err()).collect::<Vec<_>>(), vec![OhmerError::DecoderError]);
+}
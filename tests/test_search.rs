@@ -0,0 +1,44 @@
+#[macro_use(model, create)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Ohmer, Query};
+use redis::Commands;
+
+model!(
+    Post {
+        stemming;
+        stop_words;
+        text { body:String = "".to_string(); };
+        indices { lang:String = "".to_string(); };
+    });
+
+#[test]
+fn test_search() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let keys:Vec<String> = client.keys("Post:indices:body:*").unwrap();
+    for key in keys {
+        let _:bool = client.del(key).unwrap();
+    }
+
+    let p1 = create!(Post { body: "The cats are sleeping".to_string(), lang: "en".to_string(), }, &client).unwrap();
+    let p2 = create!(Post { body: "A cat and two Dogs!".to_string(), lang: "en".to_string(), }, &client).unwrap();
+    let p3 = create!(Post { body: "Dogs, dogs, dogs".to_string(), lang: "es".to_string(), }, &client).unwrap();
+
+    assert_eq!(Query::<Post>::search("body", "cat", &client).ids().unwrap(), vec![p1.id, p2.id]);
+    assert_eq!(Query::<Post>::search("body", "CATS and dog", &client).ids().unwrap(), vec![p2.id]);
+    assert_eq!(Query::<Post>::search_any("body", "sleeping dog", &client).ids().unwrap(), vec![p1.id, p2.id, p3.id]);
+    assert_eq!(Query::<Post>::search("body", "the", &client).ids().unwrap(), vec![]);
+    let mut query = Query::<Post>::search("body", "dogs", &client);
+    query.inter("lang", "es");
+    assert_eq!(query.ids().unwrap(), vec![p3.id]);
+
+    let exists:bool = client.exists("Post:indices:body:the").unwrap();
+    assert!(!exists);
+
+    let id = p2.id;
+    p2.delete(&client).unwrap();
+    assert_eq!(Query::<Post>::search("body", "cat", &client).ids().unwrap(), vec![p1.id]);
+    let member:bool = client.sismember("Post:indices:body:dog", id).unwrap();
+    assert!(!member);
+}
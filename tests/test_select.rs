@@ -0,0 +1,75 @@
+#[macro_use(model, create)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{get_fields, get_fields_as, Ohmer, Query, Reference};
+use redis::Commands;
+
+model!(
+    Stage {
+        name:String = "".to_string();
+    });
+
+model!(
+    Gig {
+        indices { band:String = "".to_string(); };
+        stage:Reference<Stage> = Reference::new();
+        notes:String = "".to_string();
+        price:u32 = 10;
+        promo:Option<String> = Some("none".to_string());
+    });
+
+#[derive(RustcDecodable, PartialEq, Debug)]
+struct GigSummary {
+    id: usize,
+    band: String,
+    price: u32,
+}
+
+#[test]
+fn test_get_fields() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let stage = create!(Stage { name: "Main".to_string(), }, &client).unwrap();
+    let mut gig = Gig::default();
+    gig.band = "The Band".to_string();
+    gig.notes = "A very long text".to_string();
+    gig.price = 25;
+    gig.promo = None;
+    gig.stage.set(&stage);
+    gig.save(&client).unwrap();
+
+    let partial = get_fields::<Gig>(gig.id, &["band", "stage", "promo"], &client).unwrap();
+    assert_eq!(partial.id, gig.id);
+    assert_eq!(&*partial.band, "The Band");
+    assert_eq!(partial.stage.get(&client).unwrap().id, stage.id);
+    assert_eq!(partial.promo, None);
+    assert_eq!(&*partial.notes, "");
+    assert_eq!(partial.price, 10);
+
+    let summary = get_fields_as::<Gig, GigSummary>(gig.id, &["band", "price"], &client).unwrap();
+    assert_eq!(summary, GigSummary { id: gig.id, band: "The Band".to_string(), price: 25 });
+}
+
+#[test]
+fn test_select() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Gig:indices:band:Trio").unwrap();
+    let g1 = create!(Gig { band: "Trio".to_string(), notes: "first".to_string(), price: 5, }, &client).unwrap();
+    let g2 = create!(Gig { band: "Trio".to_string(), notes: "second".to_string(), price: 7, }, &client).unwrap();
+
+    let query = Query::<Gig>::find("band", "Trio", &client);
+    let mut gigs = query.select(&["price"]).unwrap().collect::<Vec<_>>();
+    gigs.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(gigs.iter().map(|g| (g.id, g.price, &*g.notes)).collect::<Vec<_>>(), vec![(g1.id, 5, ""), (g2.id, 7, "")]);
+
+    let mut summaries = query.select_as::<GigSummary>(&["band", "price"]).unwrap();
+    summaries.sort_by(|a, b| a.id.cmp(&b.id));
+    assert_eq!(summaries, vec![
+            GigSummary { id: g1.id, band: "Trio".to_string(), price: 5 },
+            GigSummary { id: g2.id, band: "Trio".to_string(), price: 7 },
+            ]);
+
+    let _:bool = client.del("Gig:indices:band:Nobody").unwrap();
+    let empty = Query::<Gig>::find("band", "Nobody", &client);
+    assert_eq!(empty.select_as::<GigSummary>(&["band", "price"]).unwrap(), vec![]);
+}
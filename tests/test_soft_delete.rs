@@ -0,0 +1,85 @@
+#[macro_use(model, create, insert, len)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{all_query, deleted_query, get, restore, with, Ohmer, OhmerError, Query, Set};
+use redis::Commands;
+
+model!(
+    Reader {
+        name:String = "".to_string();
+    });
+
+model!(
+    Book {
+        soft_delete;
+        uniques { isbn:String = "".to_string(); };
+        indices { author:String = "".to_string(); };
+        title:String = "".to_string();
+        readers:Set<Reader> = Set::new();
+    });
+
+fn ids(query: Query<Book>) -> Vec<usize> {
+    query.try_into_iter().unwrap().map(|b| b.id).collect()
+}
+
+#[test]
+fn test_soft_delete() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Book:uniques:isbn").unwrap();
+    let _:bool = client.del("Book:indices:author:Tolkien").unwrap();
+
+    let reader = create!(Reader { name: "Alice".to_string(), }, &client).unwrap();
+    let book = create!(Book {
+            isbn: "978-0261103252".to_string(),
+            author: "Tolkien".to_string(),
+            title: "The Lord of the Rings".to_string(),
+            }, &client).unwrap();
+    insert!(book.readers, reader, &client).unwrap();
+    let id = book.id;
+    book.delete(&client).unwrap();
+
+    assert!(!ids(all_query::<Book>(&client).unwrap()).contains(&id));
+    assert!(ids(deleted_query::<Book>(&client).unwrap()).contains(&id));
+    assert_eq!(ids(Query::<Book>::find("author", "Tolkien", &client)), vec![]);
+    assert!(with::<Book, _>("isbn", "978-0261103252", &client).unwrap().is_none());
+
+    // the object and its relations are kept
+    let book = get::<Book>(id, &client).unwrap();
+    assert_eq!(&*book.title, "The Lord of the Rings");
+    assert_eq!(len!(book.readers, &client).unwrap(), 1);
+
+    // the unique value is available while deleted
+    let other = create!(Book { isbn: "978-0261103252".to_string(), }, &client).unwrap();
+    assert_eq!(restore::<Book>(id, &client).unwrap_err(), OhmerError::UniqueIndexViolation("isbn".to_string()));
+    other.hard_delete(&client).unwrap();
+
+    let book = restore::<Book>(id, &client).unwrap().unwrap();
+    assert_eq!(book.id, id);
+    assert!(ids(all_query::<Book>(&client).unwrap()).contains(&id));
+    assert!(!ids(deleted_query::<Book>(&client).unwrap()).contains(&id));
+    assert_eq!(ids(Query::<Book>::find("author", "Tolkien", &client)), vec![id]);
+    assert_eq!(with::<Book, _>("isbn", "978-0261103252", &client).unwrap().unwrap().id, id);
+    assert!(restore::<Book>(id, &client).unwrap().is_none());
+}
+
+#[test]
+fn test_hard_delete_after_soft_delete() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let book = create!(Book { isbn: "978-0547928227".to_string(), }, &client).unwrap();
+    let id = book.id;
+    book.delete(&client).unwrap();
+    get::<Book>(id, &client).unwrap().hard_delete(&client).unwrap();
+
+    let exists:bool = client.exists(format!("Book:{}", id)).unwrap();
+    assert!(!exists);
+    assert!(!ids(deleted_query::<Book>(&client).unwrap()).contains(&id));
+    assert!(restore::<Book>(id, &client).unwrap().is_none());
+}
+
+#[test]
+fn test_soft_delete_not_saved() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let book = Book::default();
+    assert_eq!(book.delete(&client), Err(OhmerError::NotSaved));
+}
@@ -0,0 +1,67 @@
+#[macro_use(model, create, incr)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Counter, Ohmer, Query};
+use redis::Commands;
+
+model!(
+    Team {
+        indices { league:String = "".to_string(); };
+        name:String = "".to_string();
+        city:Option<String> = None;
+        points:u32 = 0;
+        goals:Counter = Counter;
+    });
+
+fn names(teams: Vec<Team>) -> Vec<String> {
+    teams.into_iter().map(|t| t.name).collect()
+}
+
+#[test]
+fn test_sort_by() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let _:bool = client.del("Team:indices:league:premier").unwrap();
+
+    let a = create!(Team { league: "premier".to_string(), name: "Arsenal".to_string(), points: 20, city: Some("London".to_string()), }, &client).unwrap();
+    let b = create!(Team { league: "premier".to_string(), name: "Burnley".to_string(), points: 12, }, &client).unwrap();
+    let c = create!(Team { league: "premier".to_string(), name: "Chelsea".to_string(), points: 20, city: Some("London".to_string()), }, &client).unwrap();
+    let d = create!(Team { league: "premier".to_string(), name: "Derby".to_string(), points: 12, }, &client).unwrap();
+    incr!(a.goals, 30, &client).unwrap();
+    incr!(b.goals, 10, &client).unwrap();
+    incr!(c.goals, 35, &client).unwrap();
+    incr!(d.goals, 10, &client).unwrap();
+
+    let query = Query::<Team>::find("league", "premier", &client);
+
+    let teams = query.sort_by("points", false, false).then_by("goals", false, false).try_iter().unwrap().collect();
+    assert_eq!(names(teams), vec!["Chelsea", "Arsenal", "Burnley", "Derby"]);
+
+    // ties are ordered by the next key, and then by id
+    let teams = query.sort_by("points", true, false).then_by("goals", true, false).try_iter().unwrap().collect();
+    assert_eq!(names(teams), vec!["Burnley", "Derby", "Arsenal", "Chelsea"]);
+
+    let teams = query.sort_by("goals", true, false).then_by("name", false, true).limit(1, 2).try_iter().unwrap().collect();
+    assert_eq!(names(teams), vec!["Burnley", "Arsenal"]);
+
+    let rows:Vec<(usize, String, Option<String>)> = query.sort_by("points", false, false).then_by("name", true, true)
+        .rows(&["id", "name", "city"]).unwrap();
+    assert_eq!(rows, vec![
+            (a.id, "Arsenal".to_string(), Some("London".to_string())),
+            (c.id, "Chelsea".to_string(), Some("London".to_string())),
+            (b.id, "Burnley".to_string(), None),
+            (d.id, "Derby".to_string(), None),
+            ]);
+
+    let rows:Vec<(String, u32)> = query.sort_by("name", false, true).limit(0, 2).rows(&["name", "goals"]).unwrap();
+    assert_eq!(rows, vec![("Derby".to_string(), 10), ("Chelsea".to_string(), 35)]);
+
+    let ids:Vec<(usize,)> = query.sort_by("id", false, false).rows(&["id"]).unwrap();
+    assert_eq!(ids, vec![(d.id,), (c.id,), (b.id,), (a.id,)]);
+
+    let rows:Vec<(usize,)> = query.sort_by("name", true, true).rows(&[]).unwrap();
+    assert_eq!(rows, vec![]);
+
+    // as with SORT, values that are not numbers need ALPHA
+    assert!(query.sort_by("points", true, false).then_by("name", true, false).try_iter().is_err());
+}
@@ -0,0 +1,54 @@
+#[macro_use(model, create)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Ohmer, Query, StalSet};
+use redis::Commands;
+
+model!(
+    Listing {
+        indices {
+            city:String = "".to_string();
+            kind:String = "".to_string();
+        };
+        prefix { street:String = "".to_string(); };
+    });
+
+fn ttl(key: &str, client: &redis::Client) -> isize {
+    redis::cmd("TTL").arg(key).query(client).unwrap()
+}
+
+#[test]
+fn test_store() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    for key in ["Listing:indices:city:Rome", "Listing:indices:city:Milan", "Listing:indices:kind:flat", "Listing:prefix:street", "cache:listings"].iter() {
+        let _:bool = client.del(*key).unwrap();
+    }
+
+    let l1 = create!(Listing { city: "Rome".to_string(), kind: "flat".to_string(), street: "Via Appia".to_string(), }, &client).unwrap();
+    create!(Listing { city: "Milan".to_string(), kind: "house".to_string(), street: "Via Roma".to_string(), }, &client).unwrap();
+    let l3 = create!(Listing { city: "Milan".to_string(), kind: "flat".to_string(), street: "Corso Como".to_string(), }, &client).unwrap();
+
+    let mut query = Query::<Listing>::starts_with("street", "Via", &client);
+    query.union("city", "Milan").diff("kind", "house");
+    let stored = query.store("cache:listings", 60).unwrap();
+    assert_eq!(stored.ids().unwrap(), vec![l1.id, l3.id]);
+    assert!(ttl("cache:listings", &client) > 0);
+
+    // the stored set keeps the result, and can be queried further
+    let l4 = create!(Listing { city: "Milan".to_string(), kind: "flat".to_string(), street: "Via Dante".to_string(), }, &client).unwrap();
+    let mut reused = Query::<Listing>::new(StalSet::Key(b"cache:listings".to_vec()), &client);
+    assert_eq!(reused.ids().unwrap(), vec![l1.id, l3.id]);
+    reused.inter("city", "Milan");
+    assert_eq!(reused.ids().unwrap(), vec![l3.id]);
+    assert_eq!(query.ids().unwrap(), vec![l1.id, l3.id, l4.id]);
+
+    // a ttl of 0 stores the set without expiration
+    let stored = query.store("cache:listings", 0).unwrap();
+    assert_eq!(stored.ids().unwrap(), vec![l1.id, l3.id, l4.id]);
+    assert_eq!(ttl("cache:listings", &client), -1);
+
+    // the temporary keys are removed
+    let temps:Vec<String> = client.keys("ohmers:tmp:*").unwrap();
+    assert_eq!(temps.len(), 0);
+}
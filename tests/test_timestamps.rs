@@ -0,0 +1,77 @@
+#[macro_use(model, create)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use std::f64;
+use std::thread::sleep;
+use std::time::Duration;
+
+use ohmers::{get, Ohmer, Query, CreatedAt, UpdatedAt};
+use redis::Commands;
+
+model!(
+    Article {
+        indices {
+            created_at:CreatedAt = CreatedAt::new();
+        };
+        updated_at:UpdatedAt = UpdatedAt::new();
+        title:String = "".to_string();
+    });
+
+#[test]
+fn test_timestamps() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let (now, _):(u64, u64) = redis::cmd("TIME").query(&client).unwrap();
+
+    let mut article = create!(Article { title: "Hello".to_string(), }, &client).unwrap();
+    let created_at = article.created_at.time();
+    assert!(created_at >= now);
+    assert_eq!(article.updated_at.time(), created_at);
+
+    let stored:u64 = client.hget(format!("Article:{}", article.id), "created_at").unwrap();
+    assert_eq!(stored, created_at);
+
+    sleep(Duration::from_millis(1100));
+    article.title = "Hello World".to_string();
+    article.save(&client).unwrap();
+    assert_eq!(article.created_at.time(), created_at);
+    assert!(article.updated_at.time() > created_at);
+
+    let article2 = get::<Article>(article.id, &client).unwrap();
+    assert_eq!(article2.created_at.time(), created_at);
+    assert_eq!(article2.updated_at.time(), article.updated_at.time());
+    assert_eq!(&*article2.title, "Hello World");
+
+    // an update from a new struct keeps the stored creation time
+    let mut article3 = Article::default();
+    article3.set_id(article.id);
+    article3.save(&client).unwrap();
+    assert_eq!(article3.created_at.time(), created_at);
+    let stored:u64 = client.hget(format!("Article:{}", article.id), "created_at").unwrap();
+    assert_eq!(stored, created_at);
+}
+
+#[test]
+fn test_timestamps_range() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let old = create!(Article { title: "Old".to_string(), }, &client).unwrap();
+    let _:bool = client.zadd("Article:sorted:created_at", old.id, old.created_at.time() - 7200).unwrap();
+    let recent = create!(Article { title: "Recent".to_string(), }, &client).unwrap();
+    let last_hour = (recent.created_at.time() - 3600) as f64;
+
+    let ids = Query::<Article>::range("created_at", last_hour, f64::INFINITY, &client)
+        .try_into_iter().unwrap().map(|a| a.id).collect::<Vec<_>>();
+    assert!(ids.contains(&recent.id));
+    assert!(!ids.contains(&old.id));
+
+    let ids = ohmers::all_query::<Article>(&client).unwrap()
+        .inter_range("created_at", f64::NEG_INFINITY, last_hour)
+        .try_iter().unwrap().map(|a| a.id).collect::<Vec<_>>();
+    assert!(ids.contains(&old.id));
+    assert!(!ids.contains(&recent.id));
+
+    let id = recent.id;
+    recent.delete(&client).unwrap();
+    let score:Option<u64> = client.zscore("Article:sorted:created_at", id).unwrap();
+    assert_eq!(score, None);
+}
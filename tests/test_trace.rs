@@ -0,0 +1,65 @@
+#[macro_use(model, create, insert)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use std::cell::RefCell;
+
+use ohmers::{get, Ohmer, Query, Set};
+use ohmers::trace::{Trace, Traced};
+
+model!(
+    Crate {
+        indices { port:String = "".to_string(); };
+        weight:u32 = 0;
+    });
+
+model!(
+    Ship {
+        cargo:Set<Crate> = Set::new();
+    });
+
+#[test]
+fn test_explain() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let mut query = Query::<Crate>::find("port", "Rotterdam", &client);
+    query.inter_range("weight", 10.0, 20.0);
+    let commands = query.explain();
+    assert_eq!(commands.first().unwrap(), "MULTI");
+    assert_eq!(commands.last().unwrap(), "EXEC");
+    assert!(commands[1].starts_with("EVAL <RANGE_STORE> 1 ohmers:tmp:"));
+    let temp = commands[1].split(' ').nth(3).unwrap().to_string();
+    assert!(commands[1].ends_with("ZRANGEBYSCORE Crate:sorted:weight 10 20"));
+    assert!(commands.iter().any(|c| c.starts_with("SINTER") && c.contains(&*temp) && c.contains("Crate:indices:port:Rotterdam")));
+    assert_eq!(commands[commands.len() - 2], format!("DEL {}", temp));
+
+    let commands = query.sort_by("weight", false, false).explain();
+    assert!(commands.iter().any(|c| c.starts_with("SORT ") && c.ends_with(" BY Crate:*->weight DESC")));
+    let commands = query.sort_by("weight", false, false).then_by("id", true, false).explain();
+    assert!(commands.iter().any(|c| c.starts_with("EVAL <MULTI_SORT> 1 ") && c.contains(" 2 Crate:*->weight DESC")));
+}
+
+#[test]
+fn test_tracer() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    let commands = RefCell::new(vec![]);
+    let traced = Traced::new(&client, Box::new(|trace: &Trace| {
+        commands.borrow_mut().extend(trace.commands.iter().cloned())
+    }));
+
+    let item = create!(Crate { port: "Rotterdam".to_string(), weight: 12, }, &traced).unwrap();
+    let ship = create!(Ship {}, &traced).unwrap();
+    insert!(ship.cargo, item, &traced).unwrap();
+    let id = item.id;
+    get::<Crate>(id, &traced).unwrap();
+    item.delete(&traced).unwrap();
+
+    let sent = commands.borrow_mut().drain(..).collect::<Vec<_>>();
+    assert!(sent.iter().any(|c| c.contains("<SAVE>")));
+    assert!(sent.contains(&format!("SADD Ship:cargo:{} {}", ship.id, id)));
+    assert!(sent.contains(&format!("HGETALL Crate:{}", id)));
+    assert!(sent.iter().any(|c| c.contains("<DELETE>")));
+
+    // the client itself is not traced
+    create!(Crate { weight: 1, }, &client).unwrap();
+    assert_eq!(*commands.borrow(), Vec::<String>::new());
+}
@@ -0,0 +1,82 @@
+#[macro_use(model, create)] extern crate ohmers;
+extern crate rustc_serialize;
+extern crate redis;
+
+use ohmers::{Ohmer, Query, Reference};
+use redis::Commands;
+
+model!(
+    Arena {
+        indices {
+            name:String = "".to_string();
+            city:String = "".to_string();
+        };
+    });
+
+model!(
+    Match {
+        indices {
+            arena:Reference<Arena> = Reference::new();
+            sport:String = "".to_string();
+        };
+    });
+
+model!(
+    Seat {
+        indices {
+            game:Reference<Match> = Reference::new();
+        };
+    });
+
+#[test]
+fn test_via() {
+    let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+    for key in ["Arena:indices:name:Home", "Arena:indices:city:Madrid", "Arena:indices:city:Paris",
+            "Match:indices:sport:basketball"].iter() {
+        let _:bool = client.del(*key).unwrap();
+    }
+
+    let home = create!(Arena { name: "Home".to_string(), city: "Madrid".to_string(), }, &client).unwrap();
+    let away = create!(Arena { name: "Away".to_string(), city: "Madrid".to_string(), }, &client).unwrap();
+    let paris = create!(Arena { name: "Home".to_string(), city: "Paris".to_string(), }, &client).unwrap();
+    let m1 = create!(Match { arena: Reference::with_value(&home), sport: "football".to_string(), }, &client).unwrap();
+    let m2 = create!(Match { arena: Reference::with_value(&away), sport: "basketball".to_string(), }, &client).unwrap();
+    let m3 = create!(Match { arena: Reference::with_value(&paris), sport: "basketball".to_string(), }, &client).unwrap();
+    let s1 = create!(Seat { game: Reference::with_value(&m1), }, &client).unwrap();
+    let s2 = create!(Seat { game: Reference::with_value(&m3), }, &client).unwrap();
+
+    let query = Query::<Match>::via("arena", Query::<Arena>::find("name", "Home", &client));
+    assert_eq!(query.ids().unwrap(), vec![m1.id, m3.id]);
+
+    let mut arenas = Query::<Arena>::find("city", "Madrid", &client);
+    arenas.diff("name", "Home");
+    assert_eq!(Query::<Match>::via("arena", arenas).ids().unwrap(), vec![m2.id]);
+
+    let mut query = Query::<Match>::find("sport", "basketball", &client);
+    query.inter_via("arena", Query::<Arena>::find("name", "Home", &client));
+    assert_eq!(query.ids().unwrap(), vec![m3.id]);
+
+    // references of references
+    let madrid = Query::<Match>::via("arena", Query::<Arena>::find("city", "Madrid", &client));
+    assert_eq!(Query::<Seat>::via("game", madrid).ids().unwrap(), vec![s1.id]);
+    let basketball = Query::<Match>::find("sport", "basketball", &client);
+    assert_eq!(Query::<Seat>::via("game", basketball).ids().unwrap(), vec![s2.id]);
+
+    assert!(Query::<Match>::via("arena", Query::<Arena>::find("name", "Nowhere", &client)).is_empty().unwrap());
+
+    // the temporary set is removed after running
+    let commands = query.explain();
+    let temp = commands.iter().find(|c| c.starts_with("EVAL <VIA> 2 ")).unwrap().split(' ').nth(3).unwrap().to_string();
+    assert!(commands.contains(&format!("DEL {}", temp)));
+    let exists:bool = client.exists(temp).unwrap();
+    assert!(!exists);
+
+    // more members than the arguments of a single command
+    let _:bool = client.del("Arena:indices:name:Big").unwrap();
+    let big = create!(Arena { name: "Big".to_string(), }, &client).unwrap();
+    let key = format!("Match:indices:arena_id:{}", big.id);
+    let ids = (1..2501).collect::<Vec<usize>>();
+    let _:() = client.sadd(&*key, ids).unwrap();
+    assert_eq!(Query::<Match>::via("arena", Query::<Arena>::find("name", "Big", &client)).ids().unwrap().len(), 2500);
+    let _:bool = client.del(key).unwrap();
+}